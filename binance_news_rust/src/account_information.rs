@@ -0,0 +1,16 @@
+// {"makerCommission":15,"takerCommission":15,"canTrade":true,"accountType":"SPOT","balances":[{"asset":"BTC","free":"4723846.89208129","locked":"0.00000000"}],"permissions":["SPOT"]}
+use serde::Deserialize;
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct AccountInformation {
+    pub balances: Vec<Balance>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Balance {
+    pub asset: String,
+    pub free: String,
+    pub locked: String,
+}
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+//{"code":-4046,"msg":"No need to change margin type."}
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct ApiError {
+    pub code: i64,
+    pub msg: String,
+}
@@ -0,0 +1,190 @@
+use crate::decimal_string;
+use crate::exit_ladder::{Entry, ExitLadder};
+
+use fraction::Decimal;
+use log::error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error, fs, future::Future, sync::Mutex};
+
+pub const EXIT_SCHEDULE_PATH: &str = "exit_schedule.json";
+pub const SCHEDULED_BUYS_PATH: &str = "scheduled_buys.json";
+
+// Every leg rewrites the whole file, so the writes go one at a time. The scheduled buys share it
+static SCHEDULE_LOCK: Mutex<()> = Mutex::new(());
+
+// Symbol and category of every leg from its entry to the end of its exit
+static CLAIMED_LEGS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+// A running leg's hold on its symbol. The exit sweeps the whole position and cancels every
+// order on the symbol, so a second entry on top of it would lose its schedule and protection
+pub struct LegClaim {
+    symbol: String,
+    category: String,
+}
+
+impl LegClaim {
+    pub fn claim(symbol: &str, category: &str) -> Result<LegClaim, String> {
+        let mut claimed = CLAIMED_LEGS.lock().unwrap_or_else(|e| e.into_inner());
+        if claimed.iter().any(|(s, c)| s == symbol && c == category) {
+            return Err(format!("a {} leg on {} is still running", category, symbol));
+        }
+        claimed.push((symbol.to_string(), category.to_string()));
+        Ok(LegClaim {
+            symbol: symbol.to_string(),
+            category: category.to_string(),
+        })
+    }
+
+    // Keeps the claim until the leg is done, however it ends
+    pub async fn hold<F: Future>(self, leg: F) -> F::Output {
+        let output = leg.await;
+        drop(self);
+        output
+    }
+}
+
+impl Drop for LegClaim {
+    fn drop(&mut self) {
+        CLAIMED_LEGS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(s, c)| *s != self.symbol || *c != self.category);
+    }
+}
+
+// An exit ladder in progress, with everything needed to pick it up again after a restart
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingExit {
+    pub symbol: String,
+    pub category: String,
+    #[serde(with = "decimal_string")]
+    pub entry_price: Decimal,
+    #[serde(with = "decimal_string")]
+    pub original_qty: Decimal,
+    #[serde(with = "decimal_string")]
+    pub remaining_qty: Decimal,
+    // Best price seen so far, so a resumed trailing stop doesn't start over from the entry
+    #[serde(with = "decimal_string")]
+    pub peak_price: Decimal,
+    pub entry_time_ms: i64,
+    // Absolute due time of every step
+    pub due_ms: Vec<i64>,
+    pub next_step: usize,
+    pub exit_ladder: ExitLadder,
+}
+
+impl PendingExit {
+    pub fn new(
+        symbol: &str,
+        category: &str,
+        entry: Entry,
+        exit_ladder: &ExitLadder,
+        entry_time_ms: i64,
+    ) -> PendingExit {
+        PendingExit {
+            symbol: symbol.to_string(),
+            category: category.to_string(),
+            entry_price: entry.price,
+            original_qty: entry.qty,
+            remaining_qty: entry.qty,
+            peak_price: entry.price,
+            entry_time_ms,
+            due_ms: exit_ladder
+                .steps()
+                .iter()
+                .map(|step| entry_time_ms + step.time as i64 * 1000)
+                .collect(),
+            next_step: 0,
+            exit_ladder: exit_ladder.clone(),
+        }
+    }
+
+    // A symbol only has one position per category, so that is what identifies the exit
+    fn same_leg(&self, other: &PendingExit) -> bool {
+        self.symbol == other.symbol && self.category == other.category
+    }
+}
+
+// A spot buy waiting for its listing time, kept so a restart before the pair opens still buys it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledBuy {
+    pub ticker: String,
+    #[serde(with = "decimal_string")]
+    pub unit_coin_qty: Decimal,
+    pub listing_time_ms: i64,
+    pub exit_ladder: ExitLadder,
+}
+
+// A missing file means nothing was pending
+pub fn load_exits(path: &str) -> Vec<PendingExit> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    read_schedule(path)
+}
+
+pub fn save_exit(path: &str, pending_exit: &PendingExit) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut pending_exits: Vec<PendingExit> = read_schedule(path);
+    pending_exits.retain(|other| !other.same_leg(pending_exit));
+    pending_exits.push(pending_exit.clone());
+    write_schedule(path, &pending_exits)
+}
+
+pub fn remove_exit(path: &str, pending_exit: &PendingExit) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut pending_exits: Vec<PendingExit> = read_schedule(path);
+    pending_exits.retain(|other| !other.same_leg(pending_exit));
+    write_schedule(path, &pending_exits)
+}
+
+// Once everything is flattened there is nothing left to exit
+pub fn clear_exits(path: &str) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    write_schedule::<PendingExit>(path, &[])
+}
+
+pub fn load_scheduled_buys(path: &str) -> Vec<ScheduledBuy> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    read_schedule(path)
+}
+
+pub fn save_scheduled_buy(
+    path: &str,
+    scheduled_buy: &ScheduledBuy,
+) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut scheduled_buys: Vec<ScheduledBuy> = read_schedule(path);
+    scheduled_buys.retain(|other| other.ticker != scheduled_buy.ticker);
+    scheduled_buys.push(scheduled_buy.clone());
+    write_schedule(path, &scheduled_buys)
+}
+
+pub fn remove_scheduled_buy(path: &str, ticker: &str) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut scheduled_buys: Vec<ScheduledBuy> = read_schedule(path);
+    scheduled_buys.retain(|other| other.ticker != ticker);
+    write_schedule(path, &scheduled_buys)
+}
+
+// A flatten leaves nothing to buy either
+pub fn clear_scheduled_buys(path: &str) -> Result<(), Box<dyn error::Error>> {
+    let _lock = SCHEDULE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    write_schedule::<ScheduledBuy>(path, &[])
+}
+
+fn read_schedule<T: DeserializeOwned>(path: &str) -> Vec<T> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            error!("Failed to parse the schedule {}: {}", path, e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Written next to the schedule and renamed over it, so a crash mid write can't lose it
+fn write_schedule<T: Serialize>(path: &str, entries: &[T]) -> Result<(), Box<dyn error::Error>> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use fraction::Decimal;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize)]
+pub enum MarginMode {
+    Isolated,
+    Cross,
+}
+
+impl MarginMode {
+    pub fn as_margin_type(&self) -> &str {
+        match self {
+            MarginMode::Isolated => "ISOLATED",
+            MarginMode::Cross => "CROSSED",
+        }
+    }
+
+    // positionRisk reports the margin type in lowercase and as "cross" instead of "CROSSED"
+    pub fn from_margin_type(margin_type: &str) -> Option<MarginMode> {
+        match margin_type.to_lowercase().as_str() {
+            "isolated" => Some(MarginMode::Isolated),
+            "cross" | "crossed" => Some(MarginMode::Cross),
+            _ => None,
+        }
+    }
+}
+
+// Highest leverage any contract on the venue takes, what the contract itself allows is up to the
+// exchange when the leverage is set
+const MAX_LEVERAGE: u32 = 125;
+
+// Leverage and margin mode the futures leg of a rule is opened with
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct FuturesSetup {
+    pub leverage: u32,
+    pub margin_mode: MarginMode,
+}
+
+// For rules that don't give their own
+pub const DEFAULT_FUTURES_SETUP: FuturesSetup = FuturesSetup {
+    leverage: 5,
+    margin_mode: MarginMode::Isolated,
+};
+
+impl FuturesSetup {
+    // Checks a setup read from the rules file
+    pub fn validated(self) -> Result<FuturesSetup, String> {
+        if self.leverage < 1 || self.leverage > MAX_LEVERAGE {
+            return Err(format!(
+                "leverage {} is not between 1 and {}",
+                self.leverage, MAX_LEVERAGE
+            ));
+        }
+        Ok(self)
+    }
+
+    // The entry is sized off the leverage, so it only goes ahead when the account reports
+    // exactly what was requested
+    pub fn verify(&self, leverage: Decimal, margin_mode: Option<MarginMode>) -> Result<(), String> {
+        if leverage != Decimal::from(self.leverage) {
+            return Err(format!(
+                "leverage is {} instead of {}",
+                leverage, self.leverage
+            ));
+        }
+        if margin_mode != Some(self.margin_mode) {
+            return Err(format!(
+                "margin mode is {:?} instead of {:?}",
+                margin_mode, self.margin_mode
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+// The feed answers our pings, so this long without a frame means it's gone
+pub const FEED_STALE: Duration = Duration::from_secs(90);
+// No order step should take this long, one that does is stuck on the exchange or the network
+pub const EXECUTION_STALE: Duration = Duration::from_secs(60);
+// How often the dead-man's switch is re-armed and the feed is pinged
+pub const HEARTBEAT: Duration = Duration::from_secs(15);
+// The exchange cancels everything this long after the last healthy heartbeat
+pub const DEAD_MAN_WINDOW: Duration = Duration::from_secs(60);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+// Start times of the entries and exits that are sending orders right now
+static BUSY: Mutex<Vec<(u64, Instant)>> = Mutex::new(Vec::new());
+
+// Marks an entry or exit as working, until it goes idle or is dropped
+pub struct Busy {
+    id: u64,
+}
+
+impl Busy {
+    pub fn start() -> Busy {
+        let busy = Busy {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        };
+        busy.resume();
+        busy
+    }
+
+    // Waiting on a take profit is not work, it can take as long as the ladder says
+    pub fn idle(&self) {
+        BUSY.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(id, _)| *id != self.id);
+    }
+
+    pub fn resume(&self) {
+        let mut busy = BUSY.lock().unwrap_or_else(|e| e.into_inner());
+        busy.retain(|(id, _)| *id != self.id);
+        busy.push((self.id, Instant::now()));
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.idle();
+    }
+}
+
+// When the longest running entry or exit started, None if nothing is working
+pub fn busy_since() -> Option<Instant> {
+    BUSY.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(_, since)| *since)
+        .min()
+}
+
+// Healthy while the feed is alive and no entry or exit is stuck
+pub fn is_healthy(now: Instant, last_feed_frame: Instant, busy_since: Option<Instant>) -> bool {
+    now.saturating_duration_since(last_feed_frame) < FEED_STALE
+        && busy_since.is_none_or(|since| now.saturating_duration_since(since) < EXECUTION_STALE)
+}
@@ -1,82 +1,105 @@
 #[cfg(test)]
 mod test;
 
+mod account_information;
+mod api_error;
+mod decimal_string;
+mod exit_ladder;
+mod exit_schedule;
+mod futures_setup;
+mod health;
+mod instrument_rules;
+mod open_order;
+mod order_ack;
 mod position_leverage;
+mod pre_trade;
 mod price_information;
+mod price_stream;
+mod reconcile;
+mod shutdown;
+mod spot_order_response;
+mod symbol_resolution;
 mod symbols_exchange_info;
+mod ticker_aliases;
+mod title_rules;
+mod trade_stream;
+mod trailing_stop;
 mod tree_response;
 
+use account_information::{AccountInformation, Balance};
+use api_error::ApiError;
+use exit_ladder::{Entry, ExitLadder, ExitTrigger, Protection, Side, TpBasis, TpInstance};
+use exit_schedule::{LegClaim, PendingExit, ScheduledBuy, EXIT_SCHEDULE_PATH, SCHEDULED_BUYS_PATH};
+use futures_setup::{FuturesSetup, MarginMode};
+use health::Busy;
+use instrument_rules::InstrumentRules;
+use open_order::OpenOrder;
+use order_ack::OrderAck;
 use position_leverage::PositionLeverage;
+use pre_trade::PriceQuote;
 use price_information::PriceInformation;
-use symbols_exchange_info::ExchangeInfo;
+use reconcile::{Holding, OrphanPolicy};
+use shutdown::ShutdownPolicy;
+use spot_order_response::SpotOrderResponse;
+use symbol_resolution::{ResolvedSymbol, BINANCE_FUTURES, BINANCE_SPOT};
+use symbols_exchange_info::{ExchangeInfo, Filter};
+use ticker_aliases::{TickerAliases, TICKER_ALIASES_PATH};
+use title_rules::{SpotEntry, TitleRule, TitleRules, TITLE_RULES_PATH};
 use tree_response::TreeResponse;
 
-use fancy_regex::Regex;
 use fraction::Decimal;
 use futures::future::join_all;
 use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use hmac::Mac;
 use log::{error, info};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
-    Client,
+    Client, Method,
 };
 
 use std::{collections::HashMap, env, error, future::Future, pin::Pin};
+use tokio::signal::unix::{signal, Signal, SignalKind};
 use tokio::task::yield_now;
-use tokio::time::{sleep, Duration};
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, tungstenite::Result};
 
-#[derive(Eq, PartialEq, Hash, Debug)]
-enum TpCases {
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize)]
+pub enum TpCases {
     BinanceListing,
-    UpbitListing,
+    UpbitKrwListing,
+    UpbitBtcListing,
+    UpbitUsdtListing,
     BinanceFuturesListing,
     BithumbListing,
-    NoListing,
-}
-
-#[derive(Copy, Clone)]
-struct TpInstance {
-    time: u64,
-    pct: f32,
-}
-const EMPTY_TP_CASE: [TpInstance; 2] = [TpInstance { time: 0, pct: 0.0 }; 2];
-
-fn title_case(title: &str) -> Result<(&str, TpCases), Box<dyn error::Error>> {
-    if title.contains("Binance Will List") {
-        Ok((r#"\([\d]*([^()]+)\)"#, TpCases::BinanceListing))
-    } else if title.contains("마켓 디지털 자산 추가") {
-        Ok((r#"[\( ](\w*)[,\)]"#, TpCases::UpbitListing))
-    } else if title.contains("Binance Futures Will Launch USDⓈ-M") {
-        Ok((
-            r#"(?<=USDⓈ-M )\d*(.*)(?= Perpetual)"#,
-            TpCases::BinanceFuturesListing,
-        ))
-    } else if title.contains("원화 마켓 추가") {
-        Ok((r#"\([\d]*([^()]+)\)"#, TpCases::BithumbListing))
-    } else {
-        Ok(("", TpCases::NoListing))
-    }
+    CoinbaseListing,
+    CoinbaseRoadmap,
+    OkxListing,
+    BybitListing,
+    BinanceLaunchpool,
+    BinanceHodlerAirdrop,
+    BinanceMegadrop,
+    BinanceDelisting,
+    UpbitInvestmentWarning,
+    UpbitDelisting,
 }
 
-fn process_title(title: &str) -> Result<(Vec<&str>, TpCases), Box<dyn error::Error>> {
-    let (re_string, tp_case) = title_case(title)?;
-    if tp_case == TpCases::NoListing {
-        return Ok((vec![""], tp_case));
-    }
-    let re = Regex::new(re_string)?;
-    let symbols = re
-        .captures_iter(title)
-        .flatten()
-        .map(|m| m.get(1).expect("There was no group found"))
-        .map(|m| m.as_str())
-        .collect();
+// What the venues of a title rule can name
+const TITLE_RULE_VENUES: &[&str] = &["futures", "spot"];
 
-    info!("Symbol: {:?}", symbols);
+// Tickers of the first rule that matches the title, nothing if it isn't a listing
+fn process_title<'a>(
+    title_rules: &'a TitleRules,
+    title: &'a str,
+) -> (Vec<&'a str>, Option<&'a TitleRule>) {
+    let (title_rule, symbols) = match title_rules.classify(title) {
+        Some(classified) => classified,
+        None => return (Vec::new(), None),
+    };
+
+    info!("Symbol: {:?} from rule {}", symbols, title_rule.name);
 
-    Ok((symbols, tp_case))
+    (symbols, Some(title_rule))
 }
 fn generate_headers_and_signature(category: &str, payload: &str) -> (HeaderMap, String) {
     let to_sign = payload;
@@ -109,112 +132,320 @@ fn generate_headers_and_signature(category: &str, payload: &str) -> (HeaderMap,
 
 async fn update_symbol_information(
     client: Client,
-    symbols_step_size: &mut HashMap<String, f32>,
+    category: &str,
+    symbols_rules: &mut HashMap<String, InstrumentRules>,
 ) -> Result<(), Box<dyn error::Error>> {
-    if let Ok(response) = client
-        .get("https://testnet.binancefuture.com/fapi/v1/exchangeInfo")
-        .send()
-        .await
-    {
+    let url = match category {
+        "spot" => "https://testnet.binance.vision/api/v3/exchangeInfo",
+        _ => "https://testnet.binancefuture.com/fapi/v1/exchangeInfo",
+    };
+    if let Ok(response) = client.get(url).send().await {
         let body = response.text().await?;
-        let exchange_info: ExchangeInfo = serde_json::from_str(&body)?;
-        for symbol in exchange_info.symbols {
-            let quantity_precision = symbol.quantityPrecision;
-            let step_size = 10_f32.powf(-quantity_precision as f32);
-            symbols_step_size.insert(symbol.symbol.to_owned(), step_size);
-        }
+        apply_exchange_info(&body, symbols_rules)?;
+    } else {
+        error!("Failed to get {} exchange information", category);
     }
     Ok(())
 }
 
-async fn get_price(client: Client, symbol: &str) -> Result<f32, Box<dyn error::Error>> {
-    if let Ok(response) = client
-        .get("https://testnet.binancefuture.com/fapi/v1/ticker/price")
+// The rules of a single spot symbol, None while the exchange doesn't list it. Much lighter than
+// the whole exchange info for polling a pair that is about to open
+async fn get_spot_symbol_rules(
+    client: Client,
+    symbol: &str,
+) -> Result<Option<InstrumentRules>, Box<dyn error::Error>> {
+    let body = client
+        .get("https://testnet.binance.vision/api/v3/exchangeInfo")
         .query(&[("symbol", symbol)])
         .send()
-        .await
-    {
+        .await?
+        .text()
+        .await?;
+    let mut symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    // An unknown symbol is answered with an error code instead of the exchange info
+    if apply_exchange_info(&body, &mut symbols_rules).is_err() {
+        return Ok(None);
+    }
+    Ok(symbols_rules.remove(symbol))
+}
+
+fn apply_exchange_info(
+    body: &str,
+    symbols_rules: &mut HashMap<String, InstrumentRules>,
+) -> Result<(), Box<dyn error::Error>> {
+    let exchange_info: ExchangeInfo = serde_json::from_str(body)?;
+    for symbol in exchange_info.symbols {
+        // Symbols that can't be traded right now lose their rules, so no leg is opened on
+        // them
+        if symbol.status != "TRADING" {
+            symbols_rules.remove(&symbol.symbol);
+            continue;
+        }
+        let mut rules = InstrumentRules {
+            base_asset: symbol.baseAsset,
+            quote_step: Decimal::from(1) / Decimal::from(10_u64.pow(symbol.quotePrecision)),
+            ..Default::default()
+        };
+        for filter in symbol.filters {
+            match filter {
+                Filter::LotSize {
+                    minQty,
+                    maxQty,
+                    stepSize,
+                } => {
+                    rules.min_qty = minQty.parse().unwrap_or_default();
+                    rules.max_qty = maxQty.parse().unwrap_or_default();
+                    rules.step_size = stepSize.parse().unwrap_or_default();
+                }
+                Filter::MarketLotSize {
+                    minQty,
+                    maxQty,
+                    stepSize,
+                } => {
+                    rules.market_min_qty = minQty.parse().unwrap_or_default();
+                    rules.market_max_qty = maxQty.parse().unwrap_or_default();
+                    rules.market_step_size = stepSize.parse().unwrap_or_default();
+                }
+                Filter::MinNotional { minNotional } => {
+                    rules.min_notional = minNotional.parse().unwrap_or_default();
+                }
+                Filter::Notional {
+                    minNotional,
+                    maxNotional,
+                } => {
+                    rules.min_notional = minNotional.parse().unwrap_or_default();
+                    rules.max_notional = maxNotional.parse().unwrap_or_default();
+                }
+                Filter::Price {
+                    minPrice,
+                    maxPrice,
+                    tickSize,
+                } => {
+                    rules.min_price = minPrice.parse().unwrap_or_default();
+                    rules.max_price = maxPrice.parse().unwrap_or_default();
+                    rules.tick_size = tickSize.parse().unwrap_or_default();
+                }
+                Filter::Other => {}
+            }
+        }
+        symbols_rules.insert(symbol.symbol, rules);
+    }
+    Ok(())
+}
+
+async fn get_price(
+    client: Client,
+    symbol: &str,
+    category: &str,
+) -> Result<Decimal, Box<dyn error::Error>> {
+    Ok(get_price_quote(client, symbol, category).await?.price)
+}
+
+async fn get_price_quote(
+    client: Client,
+    symbol: &str,
+    category: &str,
+) -> Result<PriceQuote, Box<dyn error::Error>> {
+    let url = match category {
+        "spot" => "https://testnet.binance.vision/api/v3/ticker/price",
+        _ => "https://testnet.binancefuture.com/fapi/v1/ticker/price",
+    };
+    if let Ok(response) = client.get(url).query(&[("symbol", symbol)]).send().await {
+        let received_time = chrono::Utc::now().timestamp_millis();
         let body = response.text().await?;
         let price_information: PriceInformation =
             serde_json::from_str(&body).unwrap_or(PriceInformation {
                 price: "0.0".to_string(),
+                time: 0,
             });
-        let price = price_information.price.parse::<f32>().unwrap_or(0.0);
-        Ok(price)
+        let price: Decimal = price_information.price.parse().unwrap_or_default();
+        let time = match price_information.time {
+            0 => received_time,
+            time => time,
+        };
+        Ok(PriceQuote { price, time })
     } else {
         error!("Failed to get price for {}", symbol);
-        Ok(0.0)
+        Ok(PriceQuote {
+            price: Decimal::from(0),
+            time: 0,
+        })
     }
 }
 
-async fn get_trade_pair_leverage(
+// Signs the params the same way the order requests do, with the recv window and timestamp
+// appended, and returns the response body
+async fn send_signed_request(
     client: Client,
-    symbol: &str,
+    method: Method,
+    url: &str,
+    category: &str,
+    params: &[(&str, &str)],
     recv_window: &str,
-) -> Result<f32, Box<dyn error::Error>> {
+) -> Result<String, Box<dyn error::Error>> {
     let current_timestamp = chrono::Utc::now().timestamp_millis().to_string();
-    let payload = format!(
-        "symbol={}&recvWindow={}&timestamp={}",
-        symbol, recv_window, &current_timestamp
-    );
-    let (headers, signature) = generate_headers_and_signature("futures", &payload);
-    // Blank "" will return leverage 20, which is the default. Could be a bug due to it being the
-    // test environment
-    if let Ok(response) = client
-        .get("https://testnet.binancefuture.com/fapi/v2/positionRisk")
-        .query(&[
-            ("symbol", symbol),
-            ("recvWindow", recv_window),
-            ("timestamp", &current_timestamp),
-            ("signature", &signature),
-        ])
+    let mut query: Vec<(&str, &str)> = params.to_vec();
+    query.push(("recvWindow", recv_window));
+    query.push(("timestamp", &current_timestamp));
+    let payload = query
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>()
+        .join("&");
+    let (headers, signature) = generate_headers_and_signature(category, &payload);
+    query.push(("signature", &signature));
+    let response = client
+        .request(method, url)
+        .query(&query)
         .headers(headers)
         .send()
-        .await
+        .await?;
+    Ok(response.text().await?)
+}
+
+async fn get_position_risk(
+    client: Client,
+    symbol: &str,
+    recv_window: &str,
+) -> Result<PositionLeverage, Box<dyn error::Error>> {
+    // Blank "" will return leverage 20, which is the default. Could be a bug due to it being the
+    // test environment
+    if let Ok(body) = send_signed_request(
+        client,
+        Method::GET,
+        "https://testnet.binancefuture.com/fapi/v2/positionRisk",
+        "futures",
+        &[("symbol", symbol)],
+        recv_window,
+    )
+    .await
     {
-        let body = response.text().await?;
-        let position_risk: Vec<PositionLeverage> =
-            serde_json::from_str(&body).unwrap_or(vec![PositionLeverage {
+        let mut position_risk: Vec<PositionLeverage> =
+            serde_json::from_str(&body).unwrap_or_default();
+        if position_risk.is_empty() {
+            error!("No position risk for {}: {}", symbol, body);
+            position_risk.push(PositionLeverage {
                 leverage: "0.0".to_string(),
-            }]);
-        let leverage = position_risk[0].leverage.parse::<f32>().unwrap_or(0.0);
-        Ok(leverage)
+                ..Default::default()
+            });
+        }
+        Ok(position_risk.swap_remove(0))
     } else {
         error!("Failed to get leverage for {}", symbol);
-        Ok(0.0)
+        Ok(PositionLeverage {
+            leverage: "0.0".to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+// Applies the margin mode and leverage of the case, then reads them back from the position so
+// the entry can be sized off what the account actually uses
+async fn apply_futures_setup(
+    client: Client,
+    symbol: &str,
+    futures_setup: FuturesSetup,
+    recv_window: &str,
+) -> Result<(Decimal, Option<MarginMode>), Box<dyn error::Error>> {
+    let body = send_signed_request(
+        client.clone(),
+        Method::POST,
+        "https://testnet.binancefuture.com/fapi/v1/marginType",
+        "futures",
+        &[
+            ("symbol", symbol),
+            ("marginType", futures_setup.margin_mode.as_margin_type()),
+        ],
+        recv_window,
+    )
+    .await?;
+    // -4046 means the margin type is already set
+    match serde_json::from_str::<ApiError>(&body) {
+        Ok(api_error) if api_error.code != 200 && api_error.code != -4046 => {
+            error!(
+                "Failed to set margin type for {}: {}",
+                symbol, api_error.msg
+            )
+        }
+        _ => info!("Set margin type response {}: {}", symbol, body),
     }
+
+    let leverage = futures_setup.leverage.to_string();
+    let body = send_signed_request(
+        client.clone(),
+        Method::POST,
+        "https://testnet.binancefuture.com/fapi/v1/leverage",
+        "futures",
+        &[("symbol", symbol), ("leverage", &leverage)],
+        recv_window,
+    )
+    .await?;
+    info!("Set leverage response {}: {}", symbol, body);
+
+    let position_risk = get_position_risk(client, symbol, recv_window).await?;
+    Ok((
+        position_risk.leverage.parse().unwrap_or_default(),
+        MarginMode::from_margin_type(&position_risk.marginType),
+    ))
 }
 
-async fn market_buy_futures_position(
+// Order sides that open and close a position
+fn order_sides(side: Side) -> (&'static str, &'static str) {
+    match side {
+        Side::Long => ("BUY", "SELL"),
+        Side::Short => ("SELL", "BUY"),
+    }
+}
+
+async fn market_open_futures_position(
     client: Client,
     symbol: String,
-    size_future: f32,
-    qty_step: f32,
-    tp_instance_arr: &[TpInstance; 2],
+    size_future: Decimal,
+    futures_rules: InstrumentRules,
+    futures_setup: FuturesSetup,
+    exit_ladder: ExitLadder,
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
+    let busy = Busy::start();
     // Combine the two instances into one
-    let price: f32 = get_price(client.clone(), &symbol).await?;
-    let leverage: f32 = get_trade_pair_leverage(client.clone(), &symbol, recv_window).await?;
-
-    let size_future = Decimal::from(size_future);
-    let qty_step_dec = Decimal::from(qty_step);
-    let leverage = Decimal::from(leverage);
-    let price = Decimal::from(price);
-    let base_coin_qty = (size_future * leverage / price / qty_step_dec).floor() * qty_step_dec;
+    let (quote, setup) = futures::join!(
+        get_price_quote(client.clone(), &symbol, "futures"),
+        apply_futures_setup(client.clone(), &symbol, futures_setup, recv_window)
+    );
+    let quote = quote?;
+    let (leverage, margin_mode) = setup?;
+    let checks = pre_trade::check_price(&quote, chrono::Utc::now().timestamp_millis())
+        .and_then(|_| pre_trade::check_leverage(leverage))
+        .and_then(|_| futures_setup.verify(leverage, margin_mode));
+    if let Err(e) = checks {
+        error!("Skipping futures leg {}: {}", symbol, e);
+        return Ok(());
+    }
+    let price = quote.price;
+
+    let base_coin_qty =
+        match futures_rules.validate_market_qty(size_future * leverage / price, price) {
+            Ok(base_coin_qty) => base_coin_qty,
+            Err(e) => {
+                error!("Not opening futures {}: {}", symbol, e);
+                return Ok(());
+            }
+        };
+    let (entry_side, _) = order_sides(exit_ladder.side());
+    let quantity = futures_rules.format_qty(base_coin_qty);
     let current_timestamp = chrono::Utc::now().timestamp_millis().to_string();
     let payload = format!(
-        "symbol={}&side=BUY&type=MARKET&quantity={}&recvWindow={}&timestamp={}",
-        symbol, base_coin_qty, recv_window, &current_timestamp
+        "symbol={}&side={}&type=MARKET&quantity={}&recvWindow={}&timestamp={}",
+        symbol, entry_side, quantity, recv_window, &current_timestamp
     );
     let (headers, signature) = generate_headers_and_signature("futures", &payload);
     if let Ok(response) = client
         .post("https://testnet.binancefuture.com/fapi/v1/order")
         .query(&[
             ("symbol", symbol.as_str()),
-            ("side", "BUY"),
+            ("side", entry_side),
             ("type", "MARKET"),
-            ("quantity", &base_coin_qty.to_string()),
+            ("quantity", &quantity),
             ("recvWindow", recv_window),
             ("timestamp", &current_timestamp),
             ("signature", &signature),
@@ -224,39 +455,150 @@ async fn market_buy_futures_position(
         .await
     {
         let body = response.text().await?;
-        info!("Market buy futures position response: {}", body);
-        let base_coin_qty: f32 = base_coin_qty
-            .to_string()
-            .parse()
-            .expect("Failed to parse base coin qty");
-        market_sell_position(
-            client,
+        info!(
+            "Market {:?} futures position response: {}",
+            exit_ladder.side(),
+            body
+        );
+        let pending_exit = PendingExit::new(
             &symbol,
-            base_coin_qty,
-            qty_step,
             "futures",
-            tp_instance_arr,
-            recv_window,
-        )
-        .await?;
+            Entry {
+                qty: base_coin_qty,
+                price,
+            },
+            &exit_ladder,
+            chrono::Utc::now().timestamp_millis(),
+        );
+        drop(busy);
+        market_sell_position(client, pending_exit, &futures_rules, recv_window).await?;
         Ok(())
     } else {
-        error!("Failed to market buy futures position for {}", symbol);
+        error!(
+            "Failed to market {:?} futures position for {}",
+            exit_ladder.side(),
+            symbol
+        );
         Ok(())
     }
 }
 
+// The position size is notional over the instrument's own price, so a multiplied contract needs
+// no scaling, its multiplier only tells how many coins are held
+fn log_resolved(ticker: &str, resolved: &ResolvedSymbol) {
+    info!(
+        "{} trades as {}, {} coins per unit",
+        ticker, resolved.symbol, resolved.multiplier
+    );
+}
+
+// How long a scheduled buy keeps looking for the new pair after the listing time
+const LISTING_WAIT: Duration = Duration::from_secs(60);
+// Wait between looks for the new pair, doubled after every miss up to the max
+const LISTING_POLL_MIN: Duration = Duration::from_millis(250);
+const LISTING_POLL_MAX: Duration = Duration::from_secs(4);
+// How often the instrument lists the feed resolves tickers against are fetched again
+const INSTRUMENT_REFRESH: Duration = Duration::from_secs(15);
+// Wait before connecting to the feed again, doubled after every failed attempt up to the max
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+// Tries at a take profit before its quantity is left for the next step
+const SELL_ATTEMPTS: u32 = 3;
+
+// Buys spot once the pair opens at the announced listing time. The buy stays in the scheduled
+// buys until it is placed or given up, so a restart before then still makes it
+async fn scheduled_buy_spot_position(
+    client: Client,
+    scheduled_buy: ScheduledBuy,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let ticker = scheduled_buy.ticker.clone();
+    let done = || {
+        if let Err(e) = exit_schedule::remove_scheduled_buy(SCHEDULED_BUYS_PATH, &ticker) {
+            error!("Failed to remove the scheduled buy of {}: {}", ticker, e);
+        }
+    };
+    let listing_time =
+        chrono::DateTime::from_timestamp_millis(scheduled_buy.listing_time_ms).unwrap_or_default();
+    let until_listing = match (listing_time - chrono::Utc::now()).to_std() {
+        Ok(until_listing) => until_listing,
+        Err(_) => {
+            error!(
+                "Not buying spot {}: listing time {} has passed",
+                ticker, listing_time
+            );
+            done();
+            return Ok(());
+        }
+    };
+    info!("Buying spot {} at {}", ticker, listing_time);
+    tokio::time::sleep(until_listing).await;
+    let deadline = Instant::now() + LISTING_WAIT;
+    let mut poll_delay = LISTING_POLL_MIN;
+    while Instant::now() < deadline {
+        // Only the pairs the ticker could trade as are looked up, the ticker as announced first
+        for spot_symbol in symbol_resolution::candidates(&ticker, &BINANCE_SPOT) {
+            let spot_rules = match get_spot_symbol_rules(client.clone(), &spot_symbol.symbol).await
+            {
+                Ok(spot_rules) => spot_rules,
+                Err(e) => {
+                    error!("Failed to look up spot {}: {}", spot_symbol.symbol, e);
+                    continue;
+                }
+            };
+            if let Ok(spot_rules) = pre_trade::check_instrument(spot_rules.as_ref()) {
+                done();
+                let claim = match LegClaim::claim(&spot_symbol.symbol, "spot") {
+                    Ok(claim) => claim,
+                    Err(e) => {
+                        error!("Not buying spot {}: {}", ticker, e);
+                        return Ok(());
+                    }
+                };
+                return claim
+                    .hold(market_buy_spot_position(
+                        client,
+                        spot_symbol.symbol,
+                        scheduled_buy.unit_coin_qty,
+                        spot_rules,
+                        scheduled_buy.exit_ladder,
+                        recv_window,
+                    ))
+                    .await;
+            }
+        }
+        tokio::time::sleep(poll_delay).await;
+        poll_delay = std::cmp::min(poll_delay * 2, LISTING_POLL_MAX);
+    }
+    error!(
+        "Not buying spot {}: the pair didn't open after {}",
+        ticker, listing_time
+    );
+    done();
+    Ok(())
+}
+
 async fn market_buy_spot_position(
     client: Client,
     symbol: String,
-    unit_coin_qty: f32,
-    tp_instance_arr: &[TpInstance; 2],
+    unit_coin_qty: Decimal,
+    spot_rules: InstrumentRules,
+    exit_ladder: ExitLadder,
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
+    let busy = Busy::start();
+    let unit_coin_qty = match spot_rules.validate_quote_qty(unit_coin_qty) {
+        Ok(unit_coin_qty) => unit_coin_qty,
+        Err(e) => {
+            error!("Not buying spot {}: {}", symbol, e);
+            return Ok(());
+        }
+    };
+    let quote_order_qty = spot_rules.format_quote_qty(unit_coin_qty);
     let current_timestamp = chrono::Utc::now().timestamp_millis().to_string();
     let payload = format!(
         "symbol={}&side=BUY&type=MARKET&quoteOrderQty={}&recvWindow={}&timestamp={}",
-        symbol, unit_coin_qty, recv_window, &current_timestamp
+        symbol, quote_order_qty, recv_window, &current_timestamp
     );
     let (headers, signature) = generate_headers_and_signature("spot", &payload);
     if let Ok(response) = client
@@ -265,7 +607,7 @@ async fn market_buy_spot_position(
             ("symbol", symbol.as_str()),
             ("side", "BUY"),
             ("type", "MARKET"),
-            ("quoteOrderQty", &unit_coin_qty.to_string()),
+            ("quoteOrderQty", &quote_order_qty),
             ("recvWindow", recv_window),
             ("timestamp", &current_timestamp),
             ("signature", &signature),
@@ -276,16 +618,40 @@ async fn market_buy_spot_position(
     {
         let body = response.text().await?;
         info!("Market buy spot position response: {}", body);
-        market_sell_position(
-            client,
+        let order_response: SpotOrderResponse = match serde_json::from_str(&body) {
+            Ok(order_response) => order_response,
+            Err(_) => {
+                error!("Failed to buy spot {} {}", symbol, body);
+                return Ok(());
+            }
+        };
+        let base_coin_qty = spot_filled_base_qty(&order_response, &spot_rules.base_asset);
+        info!(
+            "Spot {} filled {} {} for {} USDT",
+            symbol, base_coin_qty, spot_rules.base_asset, order_response.cummulativeQuoteQty
+        );
+        let quote_qty: Decimal = order_response
+            .cummulativeQuoteQty
+            .parse()
+            .unwrap_or_default();
+        let executed_qty: Decimal = order_response.executedQty.parse().unwrap_or_default();
+        let entry_price = if executed_qty > Decimal::from(0) {
+            quote_qty / executed_qty
+        } else {
+            Decimal::from(0)
+        };
+        let pending_exit = PendingExit::new(
             &symbol,
-            unit_coin_qty,
-            0.0,
             "spot",
-            tp_instance_arr,
-            recv_window,
-        )
-        .await?;
+            Entry {
+                qty: base_coin_qty,
+                price: entry_price,
+            },
+            &exit_ladder,
+            chrono::Utc::now().timestamp_millis(),
+        );
+        drop(busy);
+        market_sell_position(client, pending_exit, &spot_rules, recv_window).await?;
         Ok(())
     } else {
         error!("Failed to market buy spot position for {}", symbol);
@@ -293,70 +659,918 @@ async fn market_buy_spot_position(
     }
 }
 
-async fn market_sell_position(
+// The commission is taken out of the bought coin unless it is paid in BNB, so the sellable
+// amount is the executed qty minus the fees charged in the base asset
+fn spot_filled_base_qty(order_response: &SpotOrderResponse, base_asset: &str) -> Decimal {
+    let executed_qty: Decimal = order_response.executedQty.parse().unwrap_or_default();
+    let commission = order_response
+        .fills
+        .iter()
+        .filter(|fill| fill.commissionAsset == base_asset)
+        .map(|fill| fill.commission.parse::<Decimal>().unwrap_or_default())
+        .fold(Decimal::from(0), |acc, commission| acc + commission);
+    executed_qty - commission
+}
+
+async fn get_spot_balances(
+    client: Client,
+    recv_window: &str,
+) -> Result<Vec<Balance>, Box<dyn error::Error>> {
+    let body = send_signed_request(
+        client,
+        Method::GET,
+        "https://testnet.binance.vision/api/v3/account",
+        "spot",
+        &[("omitZeroBalances", "true")],
+        recv_window,
+    )
+    .await?;
+    let account_information: AccountInformation = serde_json::from_str(&body)?;
+    Ok(account_information.balances)
+}
+
+async fn get_spot_balance(
+    client: Client,
+    asset: &str,
+    recv_window: &str,
+) -> Result<Decimal, Box<dyn error::Error>> {
+    Ok(get_spot_balances(client, recv_window)
+        .await?
+        .iter()
+        .find(|balance| balance.asset == asset)
+        .map(|balance| balance.free.parse().unwrap_or_default())
+        .unwrap_or_default())
+}
+
+async fn get_open_positions(
+    client: Client,
+    recv_window: &str,
+) -> Result<Vec<PositionLeverage>, Box<dyn error::Error>> {
+    let body = send_signed_request(
+        client,
+        Method::GET,
+        "https://testnet.binancefuture.com/fapi/v2/positionRisk",
+        "futures",
+        &[],
+        recv_window,
+    )
+    .await?;
+    let positions: Vec<PositionLeverage> = serde_json::from_str(&body)?;
+    Ok(positions
+        .into_iter()
+        .filter(|position| {
+            position.positionAmt.parse::<Decimal>().unwrap_or_default() != Decimal::from(0)
+        })
+        .collect())
+}
+
+async fn get_open_orders(
     client: Client,
-    symbol: &str,
-    qty: f32,
-    qty_step: f32,
     category: &str,
-    tp_instance_arr: &[TpInstance; 2],
+    recv_window: &str,
+) -> Result<Vec<OpenOrder>, Box<dyn error::Error>> {
+    let url = match category {
+        "futures" => "https://testnet.binancefuture.com/fapi/v1/openOrders",
+        _ => "https://testnet.binance.vision/api/v3/openOrders",
+    };
+    let body = send_signed_request(client, Method::GET, url, category, &[], recv_window).await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+// Both instrument lists updated on copies, so the feed keeps reading the ones in use while the
+// exchange info requests run and a failed one leaves them as they were
+async fn refresh_symbol_information(
+    client: Client,
+    mut futures_symbols_rules: HashMap<String, InstrumentRules>,
+    mut spot_symbols_rules: HashMap<String, InstrumentRules>,
+) -> Result<
+    (
+        HashMap<String, InstrumentRules>,
+        HashMap<String, InstrumentRules>,
+    ),
+    Box<dyn error::Error>,
+> {
+    update_symbol_information(client.clone(), "futures", &mut futures_symbols_rules).await?;
+    update_symbol_information(client, "spot", &mut spot_symbols_rules).await?;
+    Ok((futures_symbols_rules, spot_symbols_rules))
+}
+
+// Futures positions and the spot balances that trade against USDT
+async fn get_holdings(
+    client: Client,
+    spot_symbols_rules: &HashMap<String, InstrumentRules>,
+    recv_window: &str,
+) -> Result<Vec<Holding>, Box<dyn error::Error>> {
+    let mut holdings: Vec<Holding> = get_open_positions(client.clone(), recv_window)
+        .await?
+        .into_iter()
+        .map(|position| Holding {
+            symbol: position.symbol,
+            category: String::from("futures"),
+            qty: position.positionAmt.parse().unwrap_or_default(),
+            entry_price: position.entryPrice.parse().unwrap_or_default(),
+        })
+        .collect();
+    for balance in get_spot_balances(client, recv_window).await? {
+        let symbol = format!("{}USDT", balance.asset);
+        if balance.asset == "USDT" || !spot_symbols_rules.contains_key(&symbol) {
+            continue;
+        }
+        let free: Decimal = balance.free.parse().unwrap_or_default();
+        let locked: Decimal = balance.locked.parse().unwrap_or_default();
+        holdings.push(Holding {
+            symbol,
+            category: String::from("spot"),
+            qty: free + locked,
+            entry_price: Decimal::from(0),
+        });
+    }
+    Ok(holdings)
+}
+
+// Sells a spot balance at market, rounded down to what the symbol accepts
+async fn flatten_spot_balance(
+    client: Client,
+    symbol: &str,
+    qty: Decimal,
+    spot_rules: &InstrumentRules,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let price = get_price(client.clone(), symbol, "spot").await?;
+    let qty = match spot_rules.validate_market_qty(qty, price) {
+        Ok(qty) => qty,
+        Err(e) => {
+            error!("Not flattening spot {}: {}", symbol, e);
+            return Ok(());
+        }
+    };
+    let quantity = spot_rules.format_qty(qty);
+    let body = send_signed_request(
+        client,
+        Method::POST,
+        "https://testnet.binance.vision/api/v3/order",
+        "spot",
+        &[
+            ("symbol", symbol),
+            ("side", "SELL"),
+            ("type", "MARKET"),
+            ("quantity", &quantity),
+        ],
+        recv_window,
+    )
+    .await?;
+    info!("Flatten spot {} response: {}", symbol, body);
+    match serde_json::from_str::<OrderAck>(&body) {
+        Ok(order_ack) if order_ack.accepted() => Ok(()),
+        _ => Err(format!("Flatten of spot {} not taken", symbol).into()),
+    }
+}
+
+// Compares what the exchange holds with the pending exits and reports the rest. Depending on the
+// policy the orphans are left alone, put on the adopt ladder or closed at market. A short is
+// adopted on the mirrored ladder, which buys it back and takes profit below the entry
+async fn reconcile_orphans(
+    client: Client,
+    orphan_policy: OrphanPolicy,
+    futures_symbols_rules: &HashMap<String, InstrumentRules>,
+    spot_symbols_rules: &HashMap<String, InstrumentRules>,
+    adopt_ladder: &ExitLadder,
+    recv_window: &str,
+) -> Result<Vec<(PendingExit, InstrumentRules)>, Box<dyn error::Error>> {
+    let pending_exits = exit_schedule::load_exits(EXIT_SCHEDULE_PATH);
+    let holdings = get_holdings(client.clone(), spot_symbols_rules, recv_window).await?;
+    let mut open_orders = Vec::new();
+    for category in ["futures", "spot"] {
+        for open_order in get_open_orders(client.clone(), category, recv_window).await? {
+            open_orders.push((open_order.symbol, category.to_string()));
+        }
+    }
+    for (symbol, category) in reconcile::find_orphan_orders(open_orders, &pending_exits) {
+        info!("Orphaned {} orders on {}", category, symbol);
+    }
+
+    let mut adopted = Vec::new();
+    for holding in reconcile::find_orphans(holdings, &pending_exits) {
+        info!(
+            "Orphaned {} position {} of {} at {}",
+            holding.category, holding.symbol, holding.qty, holding.entry_price
+        );
+        let symbol_rules = match holding.category.as_str() {
+            "futures" => futures_symbols_rules.get(&holding.symbol),
+            _ => spot_symbols_rules.get(&holding.symbol),
+        };
+        let symbol_rules = match (orphan_policy, symbol_rules) {
+            (OrphanPolicy::Report, _) => continue,
+            (_, Some(symbol_rules)) => symbol_rules.clone(),
+            (_, None) => {
+                error!(
+                    "No rules for orphaned {} {}",
+                    holding.category, holding.symbol
+                );
+                continue;
+            }
+        };
+        // Orders on an orphan hold spot balance and would fight the new exit
+        cancel_open_orders(
+            client.clone(),
+            &holding.symbol,
+            &holding.category,
+            recv_window,
+        )
+        .await?;
+        let adopt = orphan_policy == OrphanPolicy::Adopt && holding.qty != Decimal::from(0);
+        if adopt {
+            let entry_price = if holding.entry_price > Decimal::from(0) {
+                holding.entry_price
+            } else {
+                get_price(client.clone(), &holding.symbol, &holding.category).await?
+            };
+            info!(
+                "Adopting {} {} from {}",
+                holding.category, holding.symbol, entry_price
+            );
+            // Shorts get the same ladder mirrored
+            let side = if holding.qty < Decimal::from(0) {
+                Side::Short
+            } else {
+                Side::Long
+            };
+            let pending_exit = PendingExit::new(
+                &holding.symbol,
+                &holding.category,
+                Entry {
+                    qty: side.held(holding.qty),
+                    price: entry_price,
+                },
+                &adopt_ladder.clone().with_side(side),
+                chrono::Utc::now().timestamp_millis(),
+            );
+            adopted.push((pending_exit, symbol_rules));
+        } else {
+            let closed = if holding.category == "futures" {
+                sweep_residual_position(client.clone(), &holding.symbol, &symbol_rules, recv_window)
+                    .await
+            } else {
+                flatten_spot_balance(
+                    client.clone(),
+                    &holding.symbol,
+                    holding.qty,
+                    &symbol_rules,
+                    recv_window,
+                )
+                .await
+            };
+            if let Err(e) = closed {
+                error!(
+                    "Failed to close orphan {} {}: {}",
+                    holding.category, holding.symbol, e
+                );
+            }
+        }
+    }
+    Ok(adopted)
+}
+
+// Kill switch: cancels every open order and closes every futures position, plus the spot balance
+// of every exit the bot has pending. Spot balances the bot didn't open are left alone. Failures
+// are logged and skipped so one bad symbol doesn't keep the rest open
+async fn flatten_all(
+    client: Client,
+    futures_symbols_rules: &HashMap<String, InstrumentRules>,
+    spot_symbols_rules: &HashMap<String, InstrumentRules>,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    error!("Flattening everything");
+    for category in ["futures", "spot"] {
+        let mut symbols: Vec<String> = get_open_orders(client.clone(), category, recv_window)
+            .await?
+            .into_iter()
+            .map(|open_order| open_order.symbol)
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+        for symbol in symbols {
+            if let Err(e) = cancel_open_orders(client.clone(), &symbol, category, recv_window).await
+            {
+                error!("Failed to cancel {} orders on {}: {}", category, symbol, e);
+            }
+        }
+    }
+
+    for position in get_open_positions(client.clone(), recv_window).await? {
+        let futures_rules = futures_symbols_rules
+            .get(&position.symbol)
+            .cloned()
+            .unwrap_or_default();
+        if let Err(e) = sweep_residual_position(
+            client.clone(),
+            &position.symbol,
+            &futures_rules,
+            recv_window,
+        )
+        .await
+        {
+            error!("Failed to close futures {}: {}", position.symbol, e);
+        }
+    }
+
+    for pending_exit in exit_schedule::load_exits(EXIT_SCHEDULE_PATH) {
+        if pending_exit.category != "spot" {
+            continue;
+        }
+        let spot_rules = match spot_symbols_rules.get(&pending_exit.symbol) {
+            Some(spot_rules) => spot_rules,
+            None => {
+                error!("No rules to flatten spot {}", pending_exit.symbol);
+                continue;
+            }
+        };
+        let flattened =
+            match get_spot_balance(client.clone(), &spot_rules.base_asset, recv_window).await {
+                Ok(balance) => {
+                    flatten_spot_balance(
+                        client.clone(),
+                        &pending_exit.symbol,
+                        balance,
+                        spot_rules,
+                        recv_window,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+        if let Err(e) = flattened {
+            error!("Failed to flatten spot {}: {}", pending_exit.symbol, e);
+        }
+    }
+    exit_schedule::clear_exits(EXIT_SCHEDULE_PATH)?;
+    exit_schedule::clear_scheduled_buys(SCHEDULED_BUYS_PATH)?;
+    Ok(())
+}
+
+// Picks up the exits a previous run left unfinished. Each is checked against what is still held,
+// exits whose position is gone are dropped and the rest are cut down to the live size. Steps that
+// came due while the bot was down run as soon as the exit is resumed
+async fn resume_exits(
+    client: Client,
+    futures_symbols_rules: &HashMap<String, InstrumentRules>,
+    spot_symbols_rules: &HashMap<String, InstrumentRules>,
+    recv_window: &str,
+) -> Vec<(PendingExit, InstrumentRules)> {
+    let mut resumed = Vec::new();
+    for mut pending_exit in exit_schedule::load_exits(EXIT_SCHEDULE_PATH) {
+        let symbol = pending_exit.symbol.clone();
+        let category = pending_exit.category.clone();
+        let symbol_rules = match category.as_str() {
+            "futures" => futures_symbols_rules.get(&symbol),
+            _ => spot_symbols_rules.get(&symbol),
+        };
+        let symbol_rules = match symbol_rules {
+            Some(symbol_rules) => symbol_rules.clone(),
+            None => {
+                error!(
+                    "No rules for pending {} exit {}, leaving it",
+                    category, symbol
+                );
+                continue;
+            }
+        };
+        // Protection from the previous run is placed again by the resumed exit, and spot
+        // protection holds the balance until it is cancelled
+        if let Err(e) = cancel_open_orders(client.clone(), &symbol, &category, recv_window).await {
+            error!("Failed to cancel protection {} {}: {}", category, symbol, e);
+        }
+        let held_qty = match category.as_str() {
+            "futures" => get_position_risk(client.clone(), &symbol, recv_window)
+                .await
+                .map(|position_risk| {
+                    let position_amt = position_risk.positionAmt.parse().unwrap_or_default();
+                    pending_exit.exit_ladder.side().held(position_amt)
+                }),
+            _ => get_spot_balance(client.clone(), &symbol_rules.base_asset, recv_window).await,
+        };
+        let held_qty: Decimal = match held_qty {
+            Ok(held_qty) => held_qty,
+            Err(e) => {
+                error!("Failed to reconcile {} exit {}: {}", category, symbol, e);
+                continue;
+            }
+        };
+        if held_qty <= Decimal::from(0) {
+            info!("{} {} was closed while the bot was down", category, symbol);
+            if let Err(e) = exit_schedule::remove_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+                error!(
+                    "Failed to remove the exit of {} {}: {}",
+                    category, symbol, e
+                );
+            }
+            continue;
+        }
+        pending_exit.remaining_qty = std::cmp::min(pending_exit.remaining_qty, held_qty);
+        info!(
+            "Resuming {} exit {} at step {} with {} left",
+            category, symbol, pending_exit.next_step, pending_exit.remaining_qty
+        );
+        resumed.push((pending_exit, symbol_rules));
+    }
+    resumed
+}
+
+async fn market_sell_position(
+    client: Client,
+    mut pending_exit: PendingExit,
+    symbol_rules: &InstrumentRules,
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
+    let symbol = pending_exit.symbol.clone();
+    let symbol = symbol.as_str();
+    let category = pending_exit.category.clone();
+    let category = category.as_str();
+    let exit_ladder = pending_exit.exit_ladder.clone();
+    let side = exit_ladder.side();
     let url = match category {
         "futures" => "https://testnet.binancefuture.com/fapi/v1/order",
         "spot" => "https://testnet.binance.vision/api/v3/order",
         _ => "",
     };
+    let busy = Busy::start();
+    let qty_step = symbol_rules.market_step();
+    if qty_step == Decimal::from(0) {
+        error!("No step size for {} {}, not selling", category, symbol);
+        return Ok(());
+    }
 
-    for tp in tp_instance_arr {
-        sleep(Duration::from_secs(tp.time)).await;
-        let tp_pct = Decimal::from(tp.pct);
-        let qty_step_dec = Decimal::from(qty_step);
-        let qty_dec = Decimal::from(qty);
-        let tp_qty = match category {
-            "futures" => ((qty_dec / qty_step_dec) * tp_pct).floor() * qty_step_dec,
-            "spot" => qty_dec * tp_pct,
-            _ => Decimal::from(0),
+    // Take profit times are absolute due times, so slow sells don't push back later steps and
+    // a resumed exit keeps its original timing
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let since_entry = Duration::from_millis((now_ms - pending_exit.entry_time_ms).max(0) as u64);
+    let entry_time = Instant::now()
+        .checked_sub(since_entry)
+        .unwrap_or_else(Instant::now);
+    let entry_price = pending_exit.entry_price;
+    let original_qty = (pending_exit.original_qty / qty_step).floor() * qty_step;
+    let mut remaining_qty = (pending_exit.remaining_qty / qty_step).floor() * qty_step;
+    // Without an entry price there is nothing to put the targets and the stop against
+    let watch_price = exit_ladder.watches_price() && entry_price > Decimal::from(0);
+    let mut price_stream = if watch_price {
+        price_stream::connect_price_stream(symbol, category).await
+    } else {
+        None
+    };
+    let stop_price = exit_ladder.stop_price(entry_price).filter(|_| watch_price);
+    let mut trailing_stop = exit_ladder
+        .trailing_stop(pending_exit.peak_price)
+        .filter(|_| watch_price);
+    let tp_steps = exit_ladder.steps();
+    let mut unsold = false;
+    for (index, tp) in tp_steps.iter().enumerate().skip(pending_exit.next_step) {
+        pending_exit.next_step = index;
+        pending_exit.remaining_qty = remaining_qty;
+        if let Some(trailing_stop) = trailing_stop.as_ref() {
+            pending_exit.peak_price = trailing_stop.peak();
+        }
+        if let Err(e) = exit_schedule::save_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+            error!("Failed to save the exit of {} {}: {}", category, symbol, e);
+        }
+        // Protection is placed again for every step so it follows the trailing stop and only
+        // covers what is still open
+        if watch_price && remaining_qty > Decimal::from(0) {
+            let protection = exit_ladder.protection(entry_price, index, trailing_stop.as_ref());
+            if let Err(e) = place_protective_orders(
+                client.clone(),
+                symbol,
+                category,
+                remaining_qty,
+                symbol_rules,
+                protection,
+                recv_window,
+            )
+            .await
+            {
+                error!("Failed to protect {} {}: {}", category, symbol, e);
+            }
+        }
+        let due_in = pending_exit.due_ms.get(index).copied().unwrap_or(now_ms)
+            - chrono::Utc::now().timestamp_millis();
+        busy.idle();
+        let trigger = price_stream::wait_for_exit(
+            &mut price_stream,
+            entry_time,
+            Instant::now() + Duration::from_millis(due_in.max(0) as u64),
+            tp.target_price(entry_price, side).filter(|_| watch_price),
+            stop_price,
+            &mut trailing_stop,
+            side,
+        )
+        .await;
+        busy.resume();
+        info!(
+            "Exit step {} for {} {}: {:?}",
+            index, category, symbol, trigger
+        );
+        if watch_price {
+            if let Err(e) = cancel_open_orders(client.clone(), symbol, category, recv_window).await
+            {
+                error!("Failed to cancel protection {} {}: {}", category, symbol, e);
+            }
+        }
+        let flatten = matches!(trigger, ExitTrigger::StopLoss | ExitTrigger::TrailingStop);
+        // The last take profit and the stops sell whatever is left so rounding doesn't leave
+        // a residual
+        let tp_qty = if index == tp_steps.len() - 1 || flatten {
+            remaining_qty
+        } else {
+            std::cmp::min(
+                (tp.qty(original_qty, remaining_qty) / qty_step).floor() * qty_step,
+                remaining_qty,
+            )
         };
-        let current_timestamp = chrono::Utc::now().timestamp_millis().to_string();
-        let qty_type = match category {
-            "futures" => "quantity".to_string(),
-            "spot" => "quoteOrderQty".to_string(),
-            _ => "quantity".to_string(),
+        if tp_qty <= Decimal::from(0) {
+            continue;
+        }
+        let price = if symbol_rules.min_notional > Decimal::from(0) {
+            get_price(client.clone(), symbol, category).await?
+        } else {
+            Decimal::from(0)
         };
+        let tp_qty = match symbol_rules.validate_market_qty(tp_qty, price) {
+            Ok(tp_qty) => tp_qty,
+            Err(e) => {
+                error!("Not selling {} {}: {}", category, symbol, e);
+                continue;
+            }
+        };
+        let quantity = symbol_rules.format_qty(tp_qty);
+        let (_, exit_side) = order_sides(side);
+        let mut params = vec![
+            ("symbol", symbol),
+            ("side", exit_side),
+            ("type", "MARKET"),
+            ("quantity", quantity.as_str()),
+        ];
+        // Futures exits can only shrink the position, so rounding or a partial entry can't
+        // flip it to the other side
+        if category == "futures" {
+            params.push(("reduceOnly", "true"));
+        }
 
-        let payload = format!(
-            "symbol={}&side=SELL&type=MARKET&{}={}&recvWindow={}&timestamp={}",
-            symbol, qty_type, tp_qty, recv_window, &current_timestamp
-        );
-
-        let (headers, signature) = generate_headers_and_signature(category, &payload);
-
-        if let Ok(response) = client
-            .post(url)
-            .query(&[
-                ("symbol", symbol),
-                ("side", "SELL"),
-                ("type", "MARKET"),
-                (&qty_type, &tp_qty.to_string()),
-                ("recvWindow", recv_window),
-                ("timestamp", &current_timestamp),
-                ("signature", &signature),
-            ])
-            .headers(headers)
-            .send()
+        // Only an order the exchange took counts as sold, a rejection such as -2022 or -1013
+        // leaves the quantity held
+        let mut sold = false;
+        for attempt in 1..=SELL_ATTEMPTS {
+            match send_signed_request(
+                client.clone(),
+                Method::POST,
+                url,
+                category,
+                &params,
+                recv_window,
+            )
             .await
-        {
-            let body = response.text().await?;
-            info!("Market sell position response: {}", body);
+            {
+                Ok(body) => {
+                    info!("Market sell position response: {}", body);
+                    match serde_json::from_str::<OrderAck>(&body) {
+                        Ok(order_ack) if order_ack.accepted() => {
+                            info!(
+                                "Sold {} of {} {} with order {}",
+                                quantity, category, symbol, order_ack.orderId
+                            );
+                            sold = true;
+                            break;
+                        }
+                        _ => error!(
+                            "Sell {} of {} {} not taken, attempt {}/{}",
+                            quantity, category, symbol, attempt, SELL_ATTEMPTS
+                        ),
+                    }
+                }
+                Err(e) => error!(
+                    "Failed to market sell position for {}, attempt {}/{}: {}",
+                    symbol, attempt, SELL_ATTEMPTS, e
+                ),
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        if sold {
+            remaining_qty -= tp_qty;
+            pending_exit.next_step = index + 1;
+            pending_exit.remaining_qty = remaining_qty;
+            if let Err(e) = exit_schedule::save_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+                error!("Failed to save the exit of {} {}: {}", category, symbol, e);
+            }
         } else {
-            error!("Failed to market sell position for {}", symbol);
+            error!(
+                "Gave up selling {} of {} {}, {} is still held",
+                quantity, category, symbol, remaining_qty
+            );
+        }
+        unsold = !sold;
+        if flatten {
+            break;
+        }
+    }
+
+    // The exit stays in the schedule until the position is known to be closed, so the next
+    // start resumes or reconciles what the exchange didn't take
+    if unsold {
+        return Err(format!(
+            "{} of {} {} is still held, its exit stays in the schedule",
+            remaining_qty, category, symbol
+        )
+        .into());
+    }
+    if category == "futures" {
+        sweep_residual_position(client, symbol, symbol_rules, recv_window).await?;
+    } else if remaining_qty > Decimal::from(0) {
+        // A step skipped as too small leaves its qty behind, sold now if it's enough on its own
+        let held_qty =
+            get_spot_balance(client.clone(), &symbol_rules.base_asset, recv_window).await?;
+        flatten_spot_balance(
+            client,
+            symbol,
+            std::cmp::min(remaining_qty, held_qty),
+            symbol_rules,
+            recv_window,
+        )
+        .await?;
+    }
+    exit_schedule::remove_exit(EXIT_SCHEDULE_PATH, &pending_exit)?;
+
+    Ok(())
+}
+
+// Closes whatever is left on the futures position after the last take profit, whether it's a
+// long, a short or dust under the min qty
+async fn sweep_residual_position(
+    client: Client,
+    symbol: &str,
+    futures_rules: &InstrumentRules,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let position_risk = get_position_risk(client.clone(), symbol, recv_window).await?;
+    let position_amt: Decimal = position_risk.positionAmt.parse().unwrap_or_default();
+    if position_amt == Decimal::from(0) {
+        info!("No residual position for {}", symbol);
+        return Ok(());
+    }
+    let side = if position_amt > Decimal::from(0) {
+        "SELL"
+    } else {
+        "BUY"
+    };
+    let quantity = futures_rules.format_qty(futures_rules.close_qty(position_amt));
+    info!(
+        "Sweeping residual position {} {} with a {} of {}",
+        position_amt, symbol, side, quantity
+    );
+    let body = send_signed_request(
+        client,
+        Method::POST,
+        "https://testnet.binancefuture.com/fapi/v1/order",
+        "futures",
+        &[
+            ("symbol", symbol),
+            ("side", side),
+            ("type", "MARKET"),
+            ("quantity", &quantity),
+            ("reduceOnly", "true"),
+        ],
+        recv_window,
+    )
+    .await?;
+    info!("Sweep residual position response: {}", body);
+    match serde_json::from_str::<OrderAck>(&body) {
+        Ok(order_ack) if order_ack.accepted() => Ok(()),
+        _ => Err(format!("Sweep of {} not taken", symbol).into()),
+    }
+}
+
+// Exchange side stop and take profit, so the position still gets closed if the process dies
+// before the ladder finishes. Futures orders close the whole position, spot orders hold the
+// remaining qty and have to be cancelled before selling it
+async fn place_protective_orders(
+    client: Client,
+    symbol: &str,
+    category: &str,
+    qty: Decimal,
+    symbol_rules: &InstrumentRules,
+    protection: Protection,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let (_, exit_side) = order_sides(protection.side);
+    let stop_price = protection
+        .stop_price
+        .map(|stop_price| symbol_rules.format_price(stop_price));
+    let target_price = protection
+        .target_price
+        .map(|target_price| symbol_rules.format_price(target_price));
+    let quantity = symbol_rules.format_qty(qty);
+    let mut orders: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    match category {
+        "futures" => {
+            for (order_type, trigger_price) in [
+                ("STOP_MARKET", &stop_price),
+                ("TAKE_PROFIT_MARKET", &target_price),
+            ] {
+                if let Some(trigger_price) = trigger_price {
+                    orders.push((
+                        "https://testnet.binancefuture.com/fapi/v1/order",
+                        vec![
+                            ("symbol", symbol),
+                            ("side", exit_side),
+                            ("type", order_type),
+                            ("stopPrice", trigger_price),
+                            ("closePosition", "true"),
+                            ("workingType", "MARK_PRICE"),
+                        ],
+                    ));
+                }
+            }
+        }
+        "spot" => match (&stop_price, &target_price) {
+            (Some(stop_price), Some(target_price)) => orders.push((
+                "https://testnet.binance.vision/api/v3/orderList/oco",
+                vec![
+                    ("symbol", symbol),
+                    ("side", "SELL"),
+                    ("quantity", &quantity),
+                    ("aboveType", "LIMIT_MAKER"),
+                    ("abovePrice", target_price),
+                    ("belowType", "STOP_LOSS"),
+                    ("belowStopPrice", stop_price),
+                ],
+            )),
+            (Some(stop_price), None) => orders.push((
+                "https://testnet.binance.vision/api/v3/order",
+                vec![
+                    ("symbol", symbol),
+                    ("side", "SELL"),
+                    ("type", "STOP_LOSS"),
+                    ("quantity", &quantity),
+                    ("stopPrice", stop_price),
+                ],
+            )),
+            (None, Some(target_price)) => orders.push((
+                "https://testnet.binance.vision/api/v3/order",
+                vec![
+                    ("symbol", symbol),
+                    ("side", "SELL"),
+                    ("type", "TAKE_PROFIT"),
+                    ("quantity", &quantity),
+                    ("stopPrice", target_price),
+                ],
+            )),
+            (None, None) => {}
+        },
+        _ => {}
+    }
+
+    for (url, params) in orders {
+        let body = send_signed_request(
+            client.clone(),
+            Method::POST,
+            url,
+            category,
+            &params,
+            recv_window,
+        )
+        .await?;
+        info!("Protective order for {} {}: {}", category, symbol, body);
+    }
+    Ok(())
+}
+
+// The bot only rests protective orders, so everything open on the symbol is ours to cancel
+async fn cancel_open_orders(
+    client: Client,
+    symbol: &str,
+    category: &str,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let url = match category {
+        "futures" => "https://testnet.binancefuture.com/fapi/v1/allOpenOrders",
+        "spot" => "https://testnet.binance.vision/api/v3/openOrders",
+        _ => return Ok(()),
+    };
+    let body = send_signed_request(
+        client,
+        Method::DELETE,
+        url,
+        category,
+        &[("symbol", symbol)],
+        recv_window,
+    )
+    .await?;
+    info!("Cancel open orders for {} {}: {}", category, symbol, body);
+    Ok(())
+}
+
+// Re-arms the dead-man's switch on every futures symbol with an open position. If it isn't
+// refreshed within the window the exchange cancels all open orders on the symbol, so a hung bot
+// can't leave a working order behind on a position it no longer manages
+async fn refresh_countdown_cancel_all(
+    client: Client,
+    recv_window: String,
+) -> Result<(), Box<dyn error::Error>> {
+    let countdown_time = health::DEAD_MAN_WINDOW.as_millis().to_string();
+    let mut symbols: Vec<String> = get_open_positions(client.clone(), &recv_window)
+        .await?
+        .into_iter()
+        .map(|position| position.symbol)
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+    // One symbol failing mustn't leave the others to run out
+    for symbol in symbols.iter() {
+        if let Err(e) =
+            set_countdown_cancel_all(client.clone(), symbol, &countdown_time, &recv_window).await
+        {
+            error!("Failed to arm countdown cancel all on {}: {}", symbol, e);
         }
     }
+    Ok(())
+}
 
+// Turns the dead-man's switch off on every futures symbol with an open position or open orders,
+// so what is left resting outlives the bot
+async fn disarm_countdown_cancel_all(
+    client: Client,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut symbols: Vec<String> = get_open_orders(client.clone(), "futures", recv_window)
+        .await?
+        .into_iter()
+        .map(|open_order| open_order.symbol)
+        .collect();
+    symbols.extend(
+        get_open_positions(client.clone(), recv_window)
+            .await?
+            .into_iter()
+            .map(|position| position.symbol),
+    );
+    symbols.sort();
+    symbols.dedup();
+    for symbol in symbols.iter() {
+        if let Err(e) = set_countdown_cancel_all(client.clone(), symbol, "0", recv_window).await {
+            error!("Failed to disarm countdown cancel all on {}: {}", symbol, e);
+        }
+    }
     Ok(())
 }
 
+// A countdown time of 0 cancels the countdown
+async fn set_countdown_cancel_all(
+    client: Client,
+    symbol: &str,
+    countdown_time: &str,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let body = send_signed_request(
+        client,
+        Method::POST,
+        "https://testnet.binancefuture.com/fapi/v1/countdownCancelAll",
+        "futures",
+        &[("symbol", symbol), ("countdownTime", countdown_time)],
+        recv_window,
+    )
+    .await?;
+    // Success is {"symbol":..,"countdownTime":..}, failures come back as an ApiError
+    if let Ok(api_error) = serde_json::from_str::<ApiError>(&body) {
+        error!(
+            "Failed to set countdown cancel all for {} to {}: {} {}",
+            symbol, countdown_time, api_error.code, api_error.msg
+        );
+    }
+    Ok(())
+}
+
+// Waits for the step while the exits keep running, for the time between feed connections. None
+// when SIGINT or SIGTERM comes in first
+async fn alongside_exits<T, F>(
+    step: impl Future<Output = T>,
+    exits: &mut FuturesUnordered<F>,
+    interrupt: &mut Signal,
+    terminate: &mut Signal,
+) -> Option<T>
+where
+    F: Future<Output = Result<(), Box<dyn error::Error>>>,
+{
+    tokio::pin!(step);
+    loop {
+        tokio::select! {
+            output = &mut step => return Some(output),
+            _ = interrupt.recv() => return None,
+            _ = terminate.recv() => return None,
+            Some(result) = exits.next(), if !exits.is_empty() => {
+                if let Err(e) = result {
+                    error!("Failed to process trade pair: {}", e);
+                }
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
     let args: Vec<String> = env::args().collect();
@@ -365,70 +1579,271 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
         .filter(None, log::LevelFilter::Info)
         .init();
     let client = Client::new();
-    let size_future: f32 = args
+    // `rules test "<title>"` checks a headline against the rules file and exits
+    if args.get(1).is_some_and(|arg| arg == "rules") {
+        let title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)?;
+        let title = match (args.get(2).map(String::as_str), args.get(3)) {
+            (Some("test"), Some(title)) => title,
+            _ => return Err("Usage: rules test \"<title>\"".into()),
+        };
+        match process_title(&title_rules, title) {
+            (symbols, Some(title_rule)) => info!(
+                "Rule {} matches as {:?}: {:?} on {:?}",
+                title_rule.name, title_rule.case, symbols, title_rule.venues
+            ),
+            (_, None) => info!("None of the {} rules match", title_rules.rules().len()),
+        }
+        return Ok(());
+    }
+    // `flatten [recv_window]` runs the kill switch and exits
+    if args.get(1).is_some_and(|arg| arg == "flatten") {
+        let recv_window = args.get(2).map(String::as_str).unwrap_or("1000");
+        let mut futures_symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+        update_symbol_information(client.clone(), "futures", &mut futures_symbols_rules).await?;
+        let mut spot_symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+        update_symbol_information(client.clone(), "spot", &mut spot_symbols_rules).await?;
+        return flatten_all(
+            client,
+            &futures_symbols_rules,
+            &spot_symbols_rules,
+            recv_window,
+        )
+        .await;
+    }
+    let size_future: Decimal = args
         .get(1)
         .expect("Input size for futures")
         .parse()
-        .unwrap_or(0.0);
-    let size_spot: f32 = args
+        .unwrap_or_default();
+    let size_spot: Decimal = args
         .get(2)
         .unwrap_or(&String::from("0.0"))
         .parse()
-        .unwrap_or(0.0);
+        .unwrap_or_default();
     let default_recv_window = &String::from("1000");
     let recv_window: &str = args.get(3).unwrap_or(default_recv_window);
-    let mut tp_map = HashMap::new();
-    tp_map.insert(
-        TpCases::BinanceListing,
-        [
-            TpInstance {
-                time: 2 * 60,
-                pct: 0.75,
-            },
-            TpInstance {
-                time: 8 * 60,
-                pct: 0.25,
-            },
-        ],
-    );
-    tp_map.insert(
-        TpCases::UpbitListing,
-        [
-            TpInstance {
-                time: 2 * 60,
-                pct: 0.75,
-            },
-            TpInstance {
-                time: 13 * 60,
-                pct: 0.25,
-            },
-        ],
+    let orphan_policy = args
+        .get(4)
+        .map(|arg| OrphanPolicy::from_arg(arg).expect("Orphan policy is report, adopt or flatten"))
+        .unwrap_or(OrphanPolicy::Report);
+    let shutdown_policy = args
+        .get(5)
+        .map(|arg| {
+            ShutdownPolicy::from_arg(arg)
+                .expect("Shutdown policy is flatten, persist, wait or wait:<seconds>")
+        })
+        .unwrap_or(ShutdownPolicy::Persist);
+    let mut title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)?;
+    let mut ticker_aliases = TickerAliases::load(TICKER_ALIASES_PATH)?;
+    info!(
+        "Loaded {} title rules from {}",
+        title_rules.rules().len(),
+        TITLE_RULES_PATH
     );
-    tp_map.insert(
-        TpCases::BinanceFuturesListing,
-        [
-            TpInstance { time: 7, pct: 0.5 },
-            TpInstance {
-                time: 2 * 60,
-                pct: 0.5,
-            },
-        ],
-    );
-    tp_map.insert(
-        TpCases::BithumbListing,
-        [
-            TpInstance { time: 90, pct: 1.0 },
-            TpInstance { time: 0, pct: 0.0 },
-        ],
+    // Orphaned positions get a short ladder behind a stop, since nothing is known about why they
+    // were opened
+    let adopt_ladder = ExitLadder::new(vec![TpInstance {
+        time: 5 * 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Original,
+        price_target: None,
+    }])
+    .and_then(|exit_ladder| exit_ladder.with_stop_loss(Decimal::from("0.1")))
+    .expect("Invalid exit ladder for adopted positions");
+
+    let mut futures_symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    update_symbol_information(client.clone(), "futures", &mut futures_symbols_rules).await?;
+    let mut spot_symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    update_symbol_information(client.clone(), "spot", &mut spot_symbols_rules).await?;
+    ticker_aliases.refresh(
+        futures_symbols_rules
+            .values()
+            .chain(spot_symbols_rules.values())
+            .map(|rules| rules.base_asset.as_str()),
     );
 
-    let mut symbols_step_size: HashMap<String, f32> = HashMap::new();
-    update_symbol_information(client.clone(), &mut symbols_step_size).await?;
-    loop {
+    // Exits run next to the feed, so a resumed or long ladder never holds up the next listing
+    let mut exits = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
+    for (pending_exit, symbol_rules) in resume_exits(
+        client.clone(),
+        &futures_symbols_rules,
+        &spot_symbols_rules,
+        recv_window,
+    )
+    .await
+    {
+        let claim = match LegClaim::claim(&pending_exit.symbol, &pending_exit.category) {
+            Ok(claim) => claim,
+            Err(e) => {
+                error!("Not running the exit of {}: {}", pending_exit.symbol, e);
+                continue;
+            }
+        };
+        let client = client.clone();
+        exits.push(Box::pin(claim.hold(async move {
+            market_sell_position(client, pending_exit, &symbol_rules, recv_window).await
+        })));
+    }
+    for scheduled_buy in exit_schedule::load_scheduled_buys(SCHEDULED_BUYS_PATH) {
+        info!("Resuming the scheduled buy of {}", scheduled_buy.ticker);
+        exits.push(Box::pin(scheduled_buy_spot_position(
+            client.clone(),
+            scheduled_buy,
+            recv_window,
+        )));
+    }
+    match reconcile_orphans(
+        client.clone(),
+        orphan_policy,
+        &futures_symbols_rules,
+        &spot_symbols_rules,
+        &adopt_ladder,
+        recv_window,
+    )
+    .await
+    {
+        Ok(adopted) => {
+            for (pending_exit, symbol_rules) in adopted {
+                let claim = match LegClaim::claim(&pending_exit.symbol, &pending_exit.category) {
+                    Ok(claim) => claim,
+                    Err(e) => {
+                        error!("Not running the exit of {}: {}", pending_exit.symbol, e);
+                        continue;
+                    }
+                };
+                let client = client.clone();
+                exits.push(Box::pin(claim.hold(async move {
+                    market_sell_position(client, pending_exit, &symbol_rules, recv_window).await
+                })));
+            }
+        }
+        Err(e) => error!("Failed to reconcile orphaned positions: {}", e),
+    }
+    // SIGUSR1 runs the kill switch without stopping the process
+    let mut kill_signal = signal(SignalKind::user_defined1())?;
+    let mut halted = false;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    // The dead-man's switch is only re-armed while the feed answers and no order is stuck, so a
+    // hung bot has its orders cancelled by the exchange
+    let mut heartbeat = tokio::time::interval(health::HEARTBEAT);
+    let mut healthy = true;
+    let mut countdown_refresh = FuturesUnordered::new();
+    let mut instrument_refresh_tick = tokio::time::interval(INSTRUMENT_REFRESH);
+    let mut instrument_refresh = FuturesUnordered::new();
+    let mut reconnect_delay = RECONNECT_DELAY_MIN;
+    'feed: loop {
         //wss://news.treeofalpha.com/ws ws://35.73.200.147:5050
-        if let Ok((mut socket, _)) = connect_async("wss://news.treeofalpha.com/ws").await {
-            while let Some(msg) = socket.next().await {
-                let msg = msg.unwrap_or(Message::binary(Vec::new()));
+        let connected = match alongside_exits(
+            connect_async("wss://news.treeofalpha.com/ws"),
+            &mut exits,
+            &mut interrupt,
+            &mut terminate,
+        )
+        .await
+        {
+            Some(connected) => connected,
+            None => break 'feed,
+        };
+        if let Ok((mut socket, _)) = connected {
+            reconnect_delay = RECONNECT_DELAY_MIN;
+            let mut last_feed_frame = Instant::now();
+            loop {
+                let msg = tokio::select! {
+                    msg = socket.next() => msg,
+                    Some(result) = exits.next(), if !exits.is_empty() => {
+                        if let Err(e) = result {
+                            error!("Failed to process trade pair: {}", e);
+                        }
+                        continue;
+                    }
+                    _ = instrument_refresh_tick.tick(), if instrument_refresh.is_empty() => {
+                        instrument_refresh.push(Box::pin(refresh_symbol_information(
+                            client.clone(),
+                            futures_symbols_rules.clone(),
+                            spot_symbols_rules.clone(),
+                        )));
+                        continue;
+                    }
+                    Some(refreshed) = instrument_refresh.next(),
+                        if !instrument_refresh.is_empty() =>
+                    {
+                        match refreshed {
+                            Ok((futures, spot)) => {
+                                futures_symbols_rules = futures;
+                                spot_symbols_rules = spot;
+                                ticker_aliases.refresh(
+                                    futures_symbols_rules
+                                        .values()
+                                        .chain(spot_symbols_rules.values())
+                                        .map(|rules| rules.base_asset.as_str()),
+                                );
+                            }
+                            Err(e) => error!("Failed to refresh the instrument lists: {}", e),
+                        }
+                        continue;
+                    }
+                    Some(refreshed) = countdown_refresh.next(),
+                        if !countdown_refresh.is_empty() =>
+                    {
+                        if let Err(e) = refreshed {
+                            error!("Failed to refresh countdown cancel all: {}", e);
+                        }
+                        continue;
+                    }
+                    _ = heartbeat.tick() => {
+                        let now_healthy = health::is_healthy(
+                            Instant::now(),
+                            last_feed_frame,
+                            health::busy_since(),
+                        );
+                        // The refresh runs in the background, a slow exchange mustn't hold up
+                        // the ping or the feed
+                        if now_healthy {
+                            if countdown_refresh.is_empty() {
+                                countdown_refresh.push(Box::pin(refresh_countdown_cancel_all(
+                                    client.clone(),
+                                    recv_window.to_string(),
+                                )));
+                            }
+                        } else if healthy {
+                            error!("Unhealthy, letting countdown cancel all run out");
+                        }
+                        healthy = now_healthy;
+                        // The pong is what keeps the feed counted as alive
+                        if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    _ = interrupt.recv() => break 'feed,
+                    _ = terminate.recv() => break 'feed,
+                    _ = kill_signal.recv() => {
+                        // Running exits would put their protection back, so they go first
+                        exits.clear();
+                        if let Err(e) = flatten_all(
+                            client.clone(),
+                            &futures_symbols_rules,
+                            &spot_symbols_rules,
+                            recv_window,
+                        )
+                        .await
+                        {
+                            error!("Failed to flatten everything: {}", e);
+                        }
+                        halted = true;
+                        error!("Halted, no new trades until restart");
+                        continue;
+                    }
+                };
+                let msg = match msg {
+                    Some(Ok(msg)) => {
+                        last_feed_frame = Instant::now();
+                        msg
+                    }
+                    Some(Err(_)) => Message::binary(Vec::new()),
+                    None => break,
+                };
 
                 if msg.is_text() {
                     let response = msg.to_text()?;
@@ -442,54 +1857,214 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
                         }
                     };
 
-                    let (symbols, tp_case) = process_title(&tree_response.title)?;
+                    title_rules.reload_if_changed();
+                    ticker_aliases.reload_if_changed();
+                    let (symbols, title_rule) = process_title(&title_rules, &tree_response.title);
 
                     info!("symbols = {:?}", symbols);
-                    if tp_case != TpCases::NoListing {
-                        let mut handles =
-                            FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
+                    if halted && title_rule.is_some() {
+                        info!("Halted, not trading {}", &tree_response.title);
+                    } else if let Some(title_rule) = title_rule {
+                        let size_future = size_future * title_rule.size_scale;
+                        let size_spot = size_spot * title_rule.size_scale;
+                        let listing_time = title_rules
+                            .listing_time(&tree_response.title)
+                            .or_else(|| title_rules.listing_time(&tree_response.body));
                         for symbol in symbols.iter() {
                             info!("symbol = {}", symbol);
+                            if let Err(e) = pre_trade::check_ticker(symbol) {
+                                error!("Skipping {}: {}", symbol, e);
+                                continue;
+                            }
+                            let symbol = match ticker_aliases.resolve(symbol) {
+                                Ok(asset) => {
+                                    if asset != *symbol {
+                                        info!("{} trades as {}", symbol, asset);
+                                    }
+                                    asset
+                                }
+                                // A coin bought at its listing time isn't on any venue yet
+                                Err(_) if title_rule.spot_entry == SpotEntry::AtListingTime => {
+                                    symbol
+                                }
+                                Err(e) => {
+                                    error!("Unresolvable ticker {}: {}", symbol, e);
+                                    continue;
+                                }
+                            };
 
-                            let trade_pair = format!("{}USDT", symbol);
-
-                            let tp_instance_arr = tp_map.get(&tp_case).unwrap_or(&EMPTY_TP_CASE);
-
-                            let qty_step: f32 = symbols_step_size
-                                .get(&trade_pair)
-                                .unwrap_or(&0.0)
-                                .to_owned();
-
-                            handles.push(Box::pin(market_buy_futures_position(
-                                client.clone(),
-                                trade_pair.clone(),
-                                size_future,
-                                qty_step,
-                                tp_instance_arr,
-                                recv_window,
-                            )));
-
-                            handles.push(Box::pin(market_buy_spot_position(
-                                client.clone(),
-                                trade_pair.clone(),
-                                size_spot,
-                                tp_instance_arr,
-                                recv_window,
-                            )));
-                        }
-                        while let Some(result) = handles.next().await {
-                            if let Err(e) = result {
-                                error!("Failed to process trade pair: {}", e);
+                            if title_rule.trades_on("futures") {
+                                let futures_symbol = symbol_resolution::resolve(
+                                    symbol,
+                                    &BINANCE_FUTURES,
+                                    |symbol| futures_symbols_rules.contains_key(symbol),
+                                );
+                                match pre_trade::check_size(size_future)
+                                    .and_then(|_| {
+                                        pre_trade::check_instrument(
+                                            futures_symbols_rules.get(&futures_symbol.symbol),
+                                        )
+                                    })
+                                    .and_then(|futures_rules| {
+                                        Ok((
+                                            futures_rules,
+                                            LegClaim::claim(&futures_symbol.symbol, "futures")?,
+                                        ))
+                                    }) {
+                                    Ok((futures_rules, claim)) => {
+                                        log_resolved(symbol, &futures_symbol);
+                                        exits.push(Box::pin(claim.hold(
+                                            market_open_futures_position(
+                                                client.clone(),
+                                                futures_symbol.symbol.clone(),
+                                                size_future,
+                                                futures_rules,
+                                                title_rule.futures_setup,
+                                                title_rule.exit_ladder.clone(),
+                                                recv_window,
+                                            ),
+                                        )))
+                                    }
+                                    Err(e) => error!(
+                                        "Skipping futures leg {}: {}",
+                                        futures_symbol.symbol, e
+                                    ),
+                                }
+                            }
+
+                            if title_rule.trades_on("spot")
+                                && title_rule.spot_entry == SpotEntry::AtListingTime
+                            {
+                                match pre_trade::check_size(size_spot).and_then(|_| {
+                                    listing_time.ok_or_else(|| {
+                                        "no listing time in the announcement".to_string()
+                                    })
+                                }) {
+                                    Ok(listing_time) => {
+                                        let scheduled_buy = ScheduledBuy {
+                                            ticker: symbol.to_string(),
+                                            unit_coin_qty: size_spot,
+                                            listing_time_ms: listing_time.timestamp_millis(),
+                                            exit_ladder: title_rule.exit_ladder.clone(),
+                                        };
+                                        if let Err(e) = exit_schedule::save_scheduled_buy(
+                                            SCHEDULED_BUYS_PATH,
+                                            &scheduled_buy,
+                                        ) {
+                                            error!(
+                                                "Failed to save the scheduled buy of {}: {}",
+                                                symbol, e
+                                            );
+                                        }
+                                        exits.push(Box::pin(scheduled_buy_spot_position(
+                                            client.clone(),
+                                            scheduled_buy,
+                                            recv_window,
+                                        )))
+                                    }
+                                    Err(e) => error!("Skipping spot leg {}: {}", symbol, e),
+                                }
+                            } else if title_rule.trades_on("spot") {
+                                let spot_symbol =
+                                    symbol_resolution::resolve(symbol, &BINANCE_SPOT, |symbol| {
+                                        spot_symbols_rules.contains_key(symbol)
+                                    });
+                                match pre_trade::check_size(size_spot)
+                                    .and_then(|_| {
+                                        pre_trade::check_instrument(
+                                            spot_symbols_rules.get(&spot_symbol.symbol),
+                                        )
+                                    })
+                                    .and_then(|spot_rules| {
+                                        Ok((
+                                            spot_rules,
+                                            LegClaim::claim(&spot_symbol.symbol, "spot")?,
+                                        ))
+                                    }) {
+                                    Ok((spot_rules, claim)) => {
+                                        log_resolved(symbol, &spot_symbol);
+                                        exits.push(Box::pin(claim.hold(market_buy_spot_position(
+                                            client.clone(),
+                                            spot_symbol.symbol.clone(),
+                                            size_spot,
+                                            spot_rules,
+                                            title_rule.exit_ladder.clone(),
+                                            recv_window,
+                                        ))))
+                                    }
+                                    Err(e) => {
+                                        error!("Skipping spot leg {}: {}", spot_symbol.symbol, e)
+                                    }
+                                }
                             }
                         }
                     } else {
                         info!("No listing for {}", &tree_response.title);
                     }
-                    update_symbol_information(client.clone(), &mut symbols_step_size).await?;
                 }
             }
         } else {
-            error!("Failed to connect to the server");
+            error!(
+                "Failed to connect to the server, retrying in {:?}",
+                reconnect_delay
+            );
+            if alongside_exits(
+                tokio::time::sleep(reconnect_delay),
+                &mut exits,
+                &mut interrupt,
+                &mut terminate,
+            )
+            .await
+            .is_none()
+            {
+                break 'feed;
+            }
+            reconnect_delay = std::cmp::min(reconnect_delay * 2, RECONNECT_DELAY_MAX);
         };
     }
+
+    // Leaving the feed loop drops the socket, so nothing new opens while the exits are dealt with
+    info!(
+        "Shutting down with {:?}, {} exits running",
+        shutdown_policy,
+        exits.len()
+    );
+    // Whatever the policy, the protection that stays behind mustn't be cancelled by a countdown
+    // nobody refreshes anymore
+    if let Err(e) = disarm_countdown_cancel_all(client.clone(), recv_window).await {
+        error!("Failed to disarm countdown cancel all: {}", e);
+    }
+    match shutdown_policy {
+        ShutdownPolicy::Flatten => {
+            exits.clear();
+            flatten_all(
+                client.clone(),
+                &futures_symbols_rules,
+                &spot_symbols_rules,
+                recv_window,
+            )
+            .await?;
+        }
+        ShutdownPolicy::Persist => {}
+        ShutdownPolicy::Wait(timeout) => {
+            let drain_exits = async {
+                while let Some(result) = exits.next().await {
+                    if let Err(e) = result {
+                        error!("Failed to process trade pair: {}", e);
+                    }
+                }
+            };
+            // A second signal stops the wait
+            tokio::select! {
+                drained = tokio::time::timeout(timeout, drain_exits) => match drained {
+                    Ok(()) => info!("All exits finished"),
+                    Err(_) => error!("Timed out waiting for the exits"),
+                },
+                _ = interrupt.recv() => error!("Stopped waiting for the exits"),
+                _ = terminate.recv() => error!("Stopped waiting for the exits"),
+            }
+        }
+    }
+    info!("Unfinished exits are saved in {}", EXIT_SCHEDULE_PATH);
+    Ok(())
 }
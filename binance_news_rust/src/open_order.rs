@@ -0,0 +1,8 @@
+// [{"orderId":4055398337,"symbol":"BTCUSDT","status":"NEW","clientOrderId":"web_5bNzxYkVk5KUpRyqBCRj","price":"0","origQty":"0","type":"STOP_MARKET","side":"SELL","stopPrice":"61000","closePosition":true}]
+use serde::Deserialize;
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct OpenOrder {
+    pub symbol: String,
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+//{"orderId":22542179,"symbol":"BTCUSDT","status":"NEW","clientOrderId":"testOrder","price":"0","avgPrice":"0.00000","origQty":"10","executedQty":"0","cumQuote":"0","timeInForce":"GTC","type":"MARKET","reduceOnly":true,"side":"SELL","updateTime":1566818724722}
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct OrderAck {
+    pub orderId: i64,
+    pub status: String,
+}
+
+impl OrderAck {
+    // Spot and futures answer with the order either way, a market order that found no
+    // liquidity comes back expired
+    pub fn accepted(&self) -> bool {
+        !matches!(self.status.as_str(), "EXPIRED" | "REJECTED" | "CANCELED")
+    }
+}
@@ -0,0 +1,63 @@
+use crate::instrument_rules::InstrumentRules;
+use fraction::Decimal;
+
+// Prices older than this are treated as stale and the leg is skipped
+pub const MAX_PRICE_AGE_MS: i64 = 5_000;
+
+pub struct PriceQuote {
+    pub price: Decimal,
+    // Exchange timestamp of the price in ms, or the time the response arrived when the endpoint
+    // doesn't report one
+    pub time: i64,
+}
+
+// A ticker pulled out of a headline, before it's turned into a pair. Anything but capitals and
+// digits means the pattern took in part of the sentence
+pub fn check_ticker(ticker: &str) -> Result<(), String> {
+    if ticker.is_empty() {
+        return Err("empty ticker".to_string());
+    }
+    if !ticker
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return Err(format!("{:?} is not a ticker", ticker));
+    }
+    Ok(())
+}
+
+pub fn check_size(size: Decimal) -> Result<(), String> {
+    if !size.is_finite() || size <= Decimal::from(0) {
+        return Err(format!("size {} is not positive", size));
+    }
+    Ok(())
+}
+
+pub fn check_instrument(rules: Option<&InstrumentRules>) -> Result<InstrumentRules, String> {
+    match rules {
+        Some(rules) if rules.market_step() > Decimal::from(0) => Ok(rules.clone()),
+        Some(_) => Err("the instrument has no step size".to_string()),
+        None => Err("unknown symbol".to_string()),
+    }
+}
+
+pub fn check_price(quote: &PriceQuote, now: i64) -> Result<(), String> {
+    if !quote.price.is_finite() || quote.price <= Decimal::from(0) {
+        return Err(format!("no price, got {}", quote.price));
+    }
+    if now - quote.time > MAX_PRICE_AGE_MS {
+        return Err(format!(
+            "stale price {} from {} ms ago",
+            quote.price,
+            now - quote.time
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_leverage(leverage: Decimal) -> Result<(), String> {
+    if !leverage.is_finite() || leverage < Decimal::from(1) {
+        return Err(format!("no leverage, got {}", leverage));
+    }
+    Ok(())
+}
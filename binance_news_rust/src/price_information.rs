@@ -4,4 +4,7 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct PriceInformation {
     pub price: String,
+    // Only the futures ticker reports the time of the price
+    #[serde(default)]
+    pub time: i64,
 }
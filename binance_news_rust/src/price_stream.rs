@@ -0,0 +1,105 @@
+use crate::exit_ladder::{price_trigger, ExitTrigger, Side};
+use crate::trade_stream::TradeStream;
+use crate::trailing_stop::TrailingStop;
+
+use fraction::Decimal;
+use futures::StreamExt;
+use log::{error, info};
+use tokio::net::TcpStream;
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub type PriceStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub async fn connect_price_stream(symbol: &str, category: &str) -> Option<PriceStream> {
+    let url = match category {
+        "futures" => format!(
+            "wss://stream.binancefuture.com/ws/{}@aggTrade",
+            symbol.to_lowercase()
+        ),
+        "spot" => format!(
+            "wss://testnet.binance.vision/ws/{}@trade",
+            symbol.to_lowercase()
+        ),
+        _ => return None,
+    };
+    match connect_async(url).await {
+        Ok((socket, _)) => {
+            info!("Connected {} price stream for {}", category, symbol);
+            Some(socket)
+        }
+        Err(e) => {
+            error!(
+                "Failed to connect {} price stream for {}: {}",
+                category, symbol, e
+            );
+            None
+        }
+    }
+}
+
+// Next traded price, None once the stream is closed
+async fn next_price(socket: &mut PriceStream) -> Option<Decimal> {
+    while let Some(msg) = socket.next().await {
+        let msg = msg.ok()?;
+        if !msg.is_text() {
+            continue;
+        }
+        if let Ok(trade) = serde_json::from_str::<TradeStream>(msg.to_text().ok()?) {
+            if let Ok(price) = trade.p.parse() {
+                return Some(price);
+            }
+        }
+    }
+    None
+}
+
+// Waits for the deadline, the target or either stop, whichever comes first. A dropped stream
+// falls back to the timer so the exit still happens
+pub async fn wait_for_exit(
+    price_stream: &mut Option<PriceStream>,
+    entry_time: Instant,
+    deadline: Instant,
+    target_price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    trailing_stop: &mut Option<TrailingStop>,
+    side: Side,
+) -> ExitTrigger {
+    let watch_price = target_price.is_some() || stop_price.is_some() || trailing_stop.is_some();
+    loop {
+        let socket = match price_stream {
+            Some(socket) if watch_price => socket,
+            _ => {
+                sleep_until(deadline).await;
+                return ExitTrigger::Time;
+            }
+        };
+        let price = tokio::select! {
+            _ = sleep_until(deadline) => return ExitTrigger::Time,
+            price = next_price(socket) => price,
+        };
+        match price {
+            Some(price) => {
+                if let Some(trigger) = price_trigger(price, target_price, stop_price, side) {
+                    info!("{:?} hit at {}", trigger, price);
+                    return trigger;
+                }
+                if let Some(trailing_stop) = trailing_stop.as_mut() {
+                    let elapsed_ms = entry_time.elapsed().as_millis() as u64;
+                    if trailing_stop.update(price, elapsed_ms) {
+                        info!(
+                            "Trailing stop hit at {}, peak {}",
+                            price,
+                            trailing_stop.peak()
+                        );
+                        return ExitTrigger::TrailingStop;
+                    }
+                }
+            }
+            None => {
+                error!("Price stream closed, falling back to the timer");
+                *price_stream = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+//{"symbol":"BTCUSDT","orderId":28,"orderListId":-1,"clientOrderId":"6gCrw2kRUAF9CvJDGP16IP","transactTime":1507725176595,"price":"0.00000000","origQty":"10.00000000","executedQty":"10.00000000","cummulativeQuoteQty":"10.00000000","status":"FILLED","timeInForce":"GTC","type":"MARKET","side":"SELL","fills":[{"price":"4000.00000000","qty":"1.00000000","commission":"4.00000000","commissionAsset":"USDT","tradeId":56}]}
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct SpotOrderResponse {
+    pub executedQty: String,
+    pub cummulativeQuoteQty: String,
+    pub fills: Vec<SpotOrderFill>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct SpotOrderFill {
+    pub commission: String,
+    pub commissionAsset: String,
+}
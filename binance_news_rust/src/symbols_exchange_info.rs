@@ -9,5 +9,47 @@ pub struct ExchangeInfo {
 #[derive(Deserialize)]
 pub struct Symbol {
     pub symbol: String,
-    pub quantityPrecision: i8,
+    // TRADING, or PENDING_TRADING, SETTLING, BREAK and the like when orders can't be placed
+    pub status: String,
+    pub baseAsset: String,
+    #[serde(default)]
+    pub quotePrecision: u32,
+    pub filters: Vec<Filter>,
+}
+// Futures report the min notional as "notional", spot as "minNotional". Older spot symbols
+// still report MIN_NOTIONAL, newer ones NOTIONAL
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+#[serde(tag = "filterType")]
+pub enum Filter {
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        minQty: String,
+        maxQty: String,
+        stepSize: String,
+    },
+    #[serde(rename = "MARKET_LOT_SIZE")]
+    MarketLotSize {
+        minQty: String,
+        maxQty: String,
+        stepSize: String,
+    },
+    #[serde(rename = "MIN_NOTIONAL")]
+    MinNotional {
+        #[serde(alias = "notional")]
+        minNotional: String,
+    },
+    #[serde(rename = "NOTIONAL")]
+    Notional {
+        minNotional: String,
+        maxNotional: String,
+    },
+    #[serde(rename = "PRICE_FILTER")]
+    Price {
+        minPrice: String,
+        maxPrice: String,
+        tickSize: String,
+    },
+    #[serde(other)]
+    Other,
 }
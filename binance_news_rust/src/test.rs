@@ -1,81 +1,336 @@
+use super::exit_ladder::{
+    price_trigger, Entry, ExitLadder, ExitTrigger, Side, TpBasis, TpInstance,
+};
+use super::exit_schedule::{self, LegClaim, PendingExit, ScheduledBuy};
+use super::futures_setup::{FuturesSetup, MarginMode};
 use super::generate_headers_and_signature;
+use super::get_position_risk;
 use super::get_price;
-use super::get_trade_pair_leverage;
+use super::health::{self, Busy};
+use super::instrument_rules::InstrumentRules;
+use super::order_ack::OrderAck;
+use super::pre_trade::{self, PriceQuote};
 use super::process_title;
+use super::reconcile::{self, Holding, OrphanPolicy};
+use super::shutdown::ShutdownPolicy;
+use super::spot_filled_base_qty;
+use super::spot_order_response::SpotOrderResponse;
+use super::symbol_resolution::{self, BINANCE_FUTURES, BINANCE_SPOT};
+use super::ticker_aliases::{self, TickerAliases, TICKER_ALIASES_PATH};
+use super::title_rules::{self, SpotEntry, TitleRules, TITLE_RULES_PATH};
+use super::trailing_stop::{Retrace, TrailingStop};
 use super::update_symbol_information;
 use super::TpCases;
-use fancy_regex::Regex;
+use super::TITLE_RULE_VENUES;
 use fraction::Decimal;
-use futures::{future, StreamExt};
-use hex;
 use hmac::Mac;
-use log::{error, info};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Client,
 };
 
 use std::{collections::HashMap, env, error};
-use tokio::time::{sleep, Duration};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, tungstenite::Result};
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::Result;
 
 #[test]
 fn test_process_title_variants() {
+    let title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)
+        .expect("Error loading the title rules");
     let title_binance_listing = "Binance Will List Dymension (DYM) with Seed Tag Applied";
-    let (symbol, tp_case) =
-        process_title(title_binance_listing).expect("Error processing binance listing");
+    let (symbol, title_rule) = process_title(&title_rules, title_binance_listing);
 
     assert_eq!(vec!["DYM"], symbol);
-    assert_eq!(TpCases::BinanceListing, tp_case);
+    assert_eq!(
+        Some(TpCases::BinanceListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 
     let title_upbit_listing = "KRW 마켓 디지털 자산 추가 (CTC)";
-    let (symbol, tp_case) =
-        process_title(title_upbit_listing).expect("Error processing upbit listing");
+    let (symbol, title_rule) = process_title(&title_rules, title_upbit_listing);
 
     assert_eq!(vec!["CTC"], symbol);
-    assert_eq!(TpCases::UpbitListing, tp_case);
+    assert_eq!(
+        Some(TpCases::UpbitKrwListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 
     let title_binance_futures_listing =
         "Binance Futures Will Launch USDⓈ-M ZETA Perpetual Contract With Up to 50x Leverage";
-    let (symbol, tp_case) = process_title(title_binance_futures_listing)
-        .expect("Error processing binance futures listing");
+    let (symbol, title_rule) = process_title(&title_rules, title_binance_futures_listing);
 
     assert_eq!(vec!["ZETA"], symbol);
-    assert_eq!(TpCases::BinanceFuturesListing, tp_case);
+    assert_eq!(
+        Some(TpCases::BinanceFuturesListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 
     let title_binance_futures_1000sats =
         "Binance Futures Will Launch USDⓈ-M 1000SATS Perpetual Contract With Up to 50x Leverage";
-    let (symbol, tp_case) = process_title(title_binance_futures_1000sats)
-        .expect("Error processing binance futures listing");
+    let (symbol, title_rule) = process_title(&title_rules, title_binance_futures_1000sats);
+
+    assert_eq!(vec!["1000SATS"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceFuturesListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let title_binance_futures_pair =
+        "Binance Futures Will Launch USDⓈ-M BOME and ETHFI Perpetual Contracts (2024-03-16)";
+    let (symbol, title_rule) = process_title(&title_rules, title_binance_futures_pair);
+
+    assert_eq!(vec!["BOME", "ETHFI"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceFuturesListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 
-    assert_eq!(vec!["SATS"], symbol);
-    assert_eq!(TpCases::BinanceFuturesListing, tp_case);
+    let title_binance_futures_list = "Binance Futures Will Launch USDⓈ-M ACE, NFP, AI and XAI \
+                                      Perpetual Contracts With Up to 50x Leverage, and More";
+    let (symbol, _) = process_title(&title_rules, title_binance_futures_list);
+
+    assert_eq!(vec!["ACE", "NFP", "AI", "XAI"], symbol);
+
+    let title_binance_futures_oxford_comma =
+        "Binance Futures Will Launch USDⓈ-M 1000RATS, DYM, and ZETA Perpetual Contracts";
+    let (symbol, _) = process_title(&title_rules, title_binance_futures_oxford_comma);
+
+    assert_eq!(vec!["1000RATS", "DYM", "ZETA"], symbol);
 
     let title_empty = "";
-    let (symbol, tp_case) = process_title(title_empty).expect("Error processing empty title");
+    let (symbol, title_rule) = process_title(&title_rules, title_empty);
 
-    assert_eq!(vec![""], symbol);
-    assert_eq!(TpCases::NoListing, tp_case);
+    assert!(symbol.is_empty());
+    assert!(title_rule.is_none());
 
     let title_random_text = "This is a random text";
-    let (symbol, tp_case) = process_title(title_random_text).expect("Error processing random text");
+    let (symbol, title_rule) = process_title(&title_rules, title_random_text);
 
-    assert_eq!(vec![""], symbol);
-    assert_eq!(TpCases::NoListing, tp_case);
+    assert!(symbol.is_empty());
+    assert!(title_rule.is_none());
 
     let title_bithumb_text = "맨틀(MNT) 원화 마켓 추가";
-    let (symbol, tp_case) =
-        process_title(title_bithumb_text).expect("Error processing bithumb text");
+    let (symbol, title_rule) = process_title(&title_rules, title_bithumb_text);
 
     assert_eq!(vec!["MNT"], symbol);
-    assert_eq!(TpCases::BithumbListing, tp_case);
+    assert_eq!(
+        Some(TpCases::BithumbListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 
     let multiple_upbit_listing = "KRW, BTC 마켓 디지털 자산 추가 (ALT, PYTH)";
-    let (symbol, tp_case) =
-        process_title(multiple_upbit_listing).expect("Error processing multiple upbit listing");
+    let (symbol, title_rule) = process_title(&title_rules, multiple_upbit_listing);
 
     assert_eq!(vec!["ALT", "PYTH"], symbol);
-    assert_eq!(TpCases::UpbitListing, tp_case);
+    assert_eq!(
+        Some(TpCases::UpbitKrwListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    // Without KRW the first market picks the case, and the market list isn't taken for tickers
+    let upbit_btc_listing = "셀레스티아(TIA) BTC, USDT 마켓 디지털 자산 추가";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_btc_listing);
+
+    assert_eq!(vec!["TIA"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitBtcListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+    assert!(title_rule.is_some_and(|title_rule| title_rule.size_scale < Decimal::from(1)));
+
+    let upbit_usdt_listing = "USDT 마켓 디지털 자산 추가 (ONDO, ENA)";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_usdt_listing);
+
+    assert_eq!(vec!["ONDO", "ENA"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitUsdtListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let coinbase_listing = "Coinbase will add support for Jupiter (JUP) on the Solana network";
+    let (symbol, title_rule) = process_title(&title_rules, coinbase_listing);
+
+    assert_eq!(vec!["JUP"], symbol);
+    assert_eq!(
+        Some(TpCases::CoinbaseListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let coinbase_roadmap = "Assets added to the roadmap today: Bonk (BONK), dogwifhat (WIF)";
+    let (symbol, title_rule) = process_title(&title_rules, coinbase_roadmap);
+
+    assert_eq!(vec!["BONK", "WIF"], symbol);
+    assert_eq!(
+        Some(TpCases::CoinbaseRoadmap),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let okx_listing = "OKX will list Pyth Network (PYTH) for spot trading";
+    let (symbol, title_rule) = process_title(&title_rules, okx_listing);
+
+    assert_eq!(vec!["PYTH"], symbol);
+    assert_eq!(
+        Some(TpCases::OkxListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let bybit_listing = "New Listing: ZETA/USDT — Grab a Share of the 2,000,000 ZETA Prize Pool!";
+    let (symbol, title_rule) = process_title(&title_rules, bybit_listing);
+
+    assert_eq!(vec!["ZETA"], symbol);
+    assert_eq!(
+        Some(TpCases::BybitListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let bybit_listing_pairs = "New Listing: PORTAL/USDT, SAGA/USDT";
+    let (symbol, title_rule) = process_title(&title_rules, bybit_listing_pairs);
+
+    assert_eq!(vec!["PORTAL", "SAGA"], symbol);
+    assert_eq!(
+        Some(TpCases::BybitListing),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let binance_launchpool =
+        "Introducing Ethena (ENA) on Binance Launchpool! Farm ENA by Staking BNB and FDUSD";
+    let (symbol, title_rule) = process_title(&title_rules, binance_launchpool);
+
+    assert_eq!(vec!["ENA"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceLaunchpool),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let binance_hodler_airdrop = "Binance HODLer Airdrops: Hyperlane (HYPER)";
+    let (symbol, title_rule) = process_title(&title_rules, binance_hodler_airdrop);
+
+    assert_eq!(vec!["HYPER"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceHodlerAirdrop),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let binance_hodler_project =
+        "Introducing Kaito (KAITO) as the 6th project on Binance HODLer Airdrops!";
+    let (symbol, title_rule) = process_title(&title_rules, binance_hodler_project);
+
+    assert_eq!(vec!["KAITO"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceHodlerAirdrop),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let binance_megadrop =
+        "Introducing Lista (LISTA) on Binance Megadrop! Earn Rewards by Locking BNB";
+    let (symbol, title_rule) = process_title(&title_rules, binance_megadrop);
+
+    assert_eq!(vec!["LISTA"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceMegadrop),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    assert_eq!(
+        Some(SpotEntry::AtListingTime),
+        title_rule.map(|title_rule| title_rule.spot_entry)
+    );
+
+    let binance_delisting = "Binance Will Delist ANT, MULTI, VAI, XMR on 2024-02-20";
+    let (symbol, title_rule) = process_title(&title_rules, binance_delisting);
+
+    assert_eq!(vec!["ANT", "MULTI", "VAI", "XMR"], symbol);
+    assert_eq!(
+        Some(TpCases::BinanceDelisting),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+    assert_eq!(
+        Some(Side::Short),
+        title_rule.map(|title_rule| title_rule.exit_ladder.side())
+    );
+
+    let binance_delisting_and = "Binance Will Delist BTCST, DREP, and MOB on 2024-04-10";
+    let (symbol, _) = process_title(&title_rules, binance_delisting_and);
+
+    assert_eq!(vec!["BTCST", "DREP", "MOB"], symbol);
+
+    let binance_delisting_single = "Binance Will Delist WTC on 2023-12-01";
+    let (symbol, _) = process_title(&title_rules, binance_delisting_single);
+
+    assert_eq!(vec!["WTC"], symbol);
+
+    let binance_delisting_ampersand = "Binance Will Delist WAVES, OMG, WNXM & XEM";
+    let (symbol, _) = process_title(&title_rules, binance_delisting_ampersand);
+
+    assert_eq!(vec!["WAVES", "OMG", "WNXM", "XEM"], symbol);
+
+    // Pairs and margin notices delist a market, not the coins named in them
+    let binance_delisting_margin = "Binance Will Delist USDC Margin Pairs and BTC";
+    let (symbol, title_rule) = process_title(&title_rules, binance_delisting_margin);
+
+    assert!(symbol.is_empty());
+    assert!(title_rule.is_none());
+
+    let binance_delisting_pairs =
+        "Binance Will Delist BNB/BTC, ETH/BTC & SOL/BTC Spot Trading Pairs";
+    let (symbol, title_rule) = process_title(&title_rules, binance_delisting_pairs);
+
+    assert!(symbol.is_empty());
+    assert!(title_rule.is_none());
+
+    let upbit_investment_warning = "[거래] 유의 종목 지정 안내 (PUNDIX)";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_investment_warning);
+
+    assert_eq!(vec!["PUNDIX"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitInvestmentWarning),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    // A lifted warning is good news, not a short
+    let upbit_warning_lifted = "[거래] 유의 종목 지정 해제 안내 (PUNDIX)";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_warning_lifted);
+
+    assert!(symbol.is_empty());
+    assert!(title_rule.is_none());
+
+    let upbit_delisting = "밀크(MLK), 스톰엑스(STMX) 거래지원 종료 안내";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_delisting);
+
+    assert_eq!(vec!["MLK", "STMX"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitDelisting),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+    assert_eq!(
+        Some(Side::Short),
+        title_rule.map(|title_rule| title_rule.exit_ladder.side())
+    );
+
+    let upbit_delisting_list = "[거래지원 종료] 거래지원 종료 안내 (GAS, XEM)";
+    let (symbol, _) = process_title(&title_rules, upbit_delisting_list);
+
+    assert_eq!(vec!["GAS", "XEM"], symbol);
+
+    // The markets a notice covers are not coins of it
+    let upbit_delisting_markets =
+        "[거래] 베이직어텐션토큰(BAT) 거래지원 종료 안내 (BTC, USDT 마켓)";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_delisting_markets);
+
+    assert_eq!(vec!["BAT"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitDelisting),
+        title_rule.map(|title_rule| title_rule.case)
+    );
+
+    let upbit_warning_markets = "[투자유의] 유의 종목 지정 안내 (KRW, BTC 마켓) - 웨이브(WAVES)";
+    let (symbol, title_rule) = process_title(&title_rules, upbit_warning_markets);
+
+    assert_eq!(vec!["WAVES"], symbol);
+    assert_eq!(
+        Some(TpCases::UpbitInvestmentWarning),
+        title_rule.map(|title_rule| title_rule.case)
+    );
 }
 
 #[test]
@@ -99,7 +354,6 @@ fn test_generate_headers_and_signature() {
         "X-MBX-APIKEY",
         HeaderValue::from_str(&api_key_futures).expect("Issue processing api key"),
     );
-    let payload_futures = "";
     let category_futures = "futures";
 
     let api_key_other = env::var("testnet_binance_order_key").expect("Binance_API_KEY not set");
@@ -110,7 +364,6 @@ fn test_generate_headers_and_signature() {
         "X-MBX-APIKEY",
         HeaderValue::from_str(&api_key_other).expect("Issue processing api key"),
     );
-    let payload_other = "";
     let category_other = "";
 
     let payload_btcusdt = &format!(
@@ -161,48 +414,323 @@ fn test_generate_headers_and_signature() {
 #[tokio::test]
 async fn test_symbol_hashmap() -> Result<(), Box<dyn error::Error>> {
     let client = Client::new();
-    let mut symbols_step_size: HashMap<String, f32> = HashMap::new();
-    update_symbol_information(client.clone(), &mut symbols_step_size).await?;
+    let mut symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    update_symbol_information(client.clone(), "futures", &mut symbols_rules).await?;
 
-    let mut trade_pair_assert_hashmap: HashMap<String, f32> = HashMap::new();
+    let mut trade_pair_assert_hashmap: HashMap<String, Decimal> = HashMap::new();
 
-    trade_pair_assert_hashmap.insert("BTCUSDT".to_string(), 0.001);
-    trade_pair_assert_hashmap.insert("".to_string(), 0.0);
-    trade_pair_assert_hashmap.insert("PYTHUSDT".to_string(), 1.0);
-    trade_pair_assert_hashmap.insert("ETCUSDT".to_string(), 0.01);
-    trade_pair_assert_hashmap.insert("TWTUSDT".to_string(), 0.1);
+    trade_pair_assert_hashmap.insert("BTCUSDT".to_string(), Decimal::from("0.001"));
+    trade_pair_assert_hashmap.insert("".to_string(), Decimal::from(0));
+    trade_pair_assert_hashmap.insert("PYTHUSDT".to_string(), Decimal::from(1));
+    trade_pair_assert_hashmap.insert("ETCUSDT".to_string(), Decimal::from("0.01"));
+    trade_pair_assert_hashmap.insert("TWTUSDT".to_string(), Decimal::from("0.1"));
 
     for (trade_pair, qty_step_assert) in trade_pair_assert_hashmap {
-        let qty_step: f32 = symbols_step_size
+        let qty_step: Decimal = symbols_rules
             .get(&trade_pair)
-            .unwrap_or(&0.0)
-            .to_owned();
+            .map(|rules| rules.step_size)
+            .unwrap_or_default();
         assert_eq!(qty_step_assert, qty_step);
     }
 
     Ok(())
 }
 
+#[tokio::test]
+async fn test_spot_symbol_hashmap() -> Result<(), Box<dyn error::Error>> {
+    let client = Client::new();
+    let mut spot_symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    update_symbol_information(client.clone(), "spot", &mut spot_symbols_rules).await?;
+
+    let btc_rules = spot_symbols_rules
+        .get("BTCUSDT")
+        .expect("BTCUSDT spot rules missing");
+
+    assert_eq!("BTC", btc_rules.base_asset);
+    assert_eq!(Decimal::from("0.00001"), btc_rules.step_size);
+    assert_ne!(Decimal::from(0), btc_rules.min_notional);
+    assert_ne!(Decimal::from(0), btc_rules.tick_size);
+    assert_eq!(Decimal::from("0.00000001"), btc_rules.quote_step);
+    assert!(!spot_symbols_rules.contains_key(""));
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_market_qty() {
+    let rules = InstrumentRules {
+        step_size: Decimal::from("0.001"),
+        min_qty: Decimal::from("0.001"),
+        max_qty: Decimal::from(1000),
+        market_step_size: Decimal::from("0.01"),
+        market_min_qty: Decimal::from("0.01"),
+        market_max_qty: Decimal::from(120),
+        min_notional: Decimal::from(5),
+        tick_size: Decimal::from("0.1"),
+        ..Default::default()
+    };
+    let price = Decimal::from(100);
+
+    let qty = rules
+        .validate_market_qty(Decimal::from("1.23456"), price)
+        .expect("Qty should be valid");
+    assert_eq!(Decimal::from("1.23"), qty);
+
+    let qty = rules
+        .validate_market_qty(Decimal::from(500), price)
+        .expect("Qty should be clamped");
+    assert_eq!(Decimal::from(120), qty);
+
+    assert!(rules
+        .validate_market_qty(Decimal::from("0.009"), price)
+        .is_err());
+    assert!(rules
+        .validate_market_qty(Decimal::from("0.04"), price)
+        .is_err());
+    assert!(rules
+        .validate_market_qty(Decimal::from(1), Decimal::from(0))
+        .is_err());
+    assert!(InstrumentRules::default()
+        .validate_market_qty(Decimal::from(1), price)
+        .is_err());
+
+    let spot_rules = InstrumentRules {
+        min_notional: Decimal::from(5),
+        max_notional: Decimal::from(9000),
+        ..Default::default()
+    };
+    assert_eq!(
+        Decimal::from(9000),
+        spot_rules
+            .validate_quote_qty(Decimal::from(10000))
+            .expect("Quote qty should be clamped")
+    );
+    assert!(spot_rules.validate_quote_qty(Decimal::from(4)).is_err());
+}
+
+#[test]
+fn test_format_qty() {
+    let rules = InstrumentRules {
+        step_size: "0.00100000".parse().expect("Error parsing step size"),
+        quote_step: Decimal::from("0.01"),
+        ..Default::default()
+    };
+
+    assert_eq!("1.234", rules.format_qty(Decimal::from("1.23456")));
+    assert_eq!("120", rules.format_qty(Decimal::from(120)));
+    assert_eq!(
+        "0.001",
+        rules.format_qty(Decimal::from("0.0001234") * Decimal::from(10))
+    );
+    assert_eq!("100.5", rules.format_quote_qty(Decimal::from("100.5")));
+
+    let whole_rules = InstrumentRules {
+        step_size: Decimal::from(1),
+        ..Default::default()
+    };
+    assert_eq!(
+        "81",
+        whole_rules.format_qty(Decimal::from(100) / Decimal::from("1.23"))
+    );
+}
+
+#[test]
+fn test_close_qty() {
+    let rules = InstrumentRules {
+        step_size: Decimal::from("0.01"),
+        min_qty: Decimal::from("0.05"),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Decimal::from("1.24"),
+        rules.close_qty(Decimal::from("1.234"))
+    );
+    assert_eq!(Decimal::from("0.3"), rules.close_qty(Decimal::from("-0.3")));
+    assert_eq!(
+        Decimal::from("0.05"),
+        rules.close_qty(Decimal::from("0.001"))
+    );
+}
+
+#[test]
+fn test_pre_trade_checks() {
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut symbols_rules: HashMap<String, InstrumentRules> = HashMap::new();
+    symbols_rules.insert(
+        "BTCUSDT".to_string(),
+        InstrumentRules {
+            step_size: Decimal::from("0.001"),
+            ..Default::default()
+        },
+    );
+    symbols_rules.insert("DUSTUSDT".to_string(), InstrumentRules::default());
+
+    assert!(pre_trade::check_instrument(symbols_rules.get("BTCUSDT")).is_ok());
+    assert!(pre_trade::check_instrument(symbols_rules.get("DUSTUSDT")).is_err());
+    assert!(pre_trade::check_instrument(symbols_rules.get("SATSUSDT")).is_err());
+
+    assert!(pre_trade::check_ticker("1000SATS").is_ok());
+    assert!(pre_trade::check_ticker("ABC and XYZ").is_err());
+    assert!(pre_trade::check_ticker("").is_err());
+
+    assert!(pre_trade::check_size(Decimal::from(100)).is_ok());
+    assert!(pre_trade::check_size(Decimal::from(0)).is_err());
+
+    let fresh_quote = PriceQuote {
+        price: Decimal::from(43000),
+        time: now - 100,
+    };
+    let zero_quote = PriceQuote {
+        price: Decimal::from(0),
+        time: now,
+    };
+    let stale_quote = PriceQuote {
+        price: Decimal::from(43000),
+        time: now - pre_trade::MAX_PRICE_AGE_MS - 1,
+    };
+    assert!(pre_trade::check_price(&fresh_quote, now).is_ok());
+    assert!(pre_trade::check_price(&zero_quote, now).is_err());
+    assert!(pre_trade::check_price(&stale_quote, now).is_err());
+
+    assert!(pre_trade::check_leverage(Decimal::from(20)).is_ok());
+    assert!(pre_trade::check_leverage(Decimal::from(0)).is_err());
+}
+
+#[test]
+fn test_symbol_resolution() {
+    assert_eq!(
+        ("SATS", 1000),
+        symbol_resolution::split_multiplier("1000SATS")
+    );
+    assert_eq!(
+        ("MOG", 1_000_000),
+        symbol_resolution::split_multiplier("1000000MOG")
+    );
+    assert_eq!(
+        ("BABYDOGE", 1_000_000),
+        symbol_resolution::split_multiplier("1MBABYDOGE")
+    );
+    assert_eq!(("1INCH", 1), symbol_resolution::split_multiplier("1INCH"));
+    assert_eq!(("ZETA", 1), symbol_resolution::split_multiplier("ZETA"));
+
+    let futures = ["1000SATSUSDT", "1MBABYDOGEUSDT", "ZETAUSDT", "1INCHUSDT"];
+    let spot = ["1000SATSUSDT", "BABYDOGEUSDT", "ZETAUSDT"];
+    let on_futures = |ticker| {
+        symbol_resolution::resolve(ticker, &BINANCE_FUTURES, |symbol| futures.contains(&symbol))
+    };
+    let on_spot =
+        |ticker| symbol_resolution::resolve(ticker, &BINANCE_SPOT, |symbol| spot.contains(&symbol));
+
+    // The prefix is kept when the venue lists it and found when the headline leaves it out
+    let resolved = on_futures("1000SATS");
+    assert_eq!("1000SATSUSDT", resolved.symbol);
+    assert_eq!(Decimal::from(1000), resolved.multiplier);
+    assert_eq!("1000SATSUSDT", on_futures("SATS").symbol);
+    assert_eq!("1000SATSUSDT", on_spot("SATS").symbol);
+
+    // The same coin is named differently on spot and perps
+    let resolved = on_futures("BABYDOGE");
+    assert_eq!("1MBABYDOGEUSDT", resolved.symbol);
+    assert_eq!(Decimal::from(1_000_000), resolved.multiplier);
+    let resolved = on_spot("BABYDOGE");
+    assert_eq!("BABYDOGEUSDT", resolved.symbol);
+    assert_eq!(Decimal::from(1), resolved.multiplier);
+
+    assert_eq!("ZETAUSDT", on_futures("ZETA").symbol);
+    assert_eq!("1INCHUSDT", on_futures("1INCH").symbol);
+
+    // Nothing listed leaves the ticker as announced, for the leg checks to turn down
+    assert_eq!("NEWUSDT", on_futures("NEW").symbol);
+}
+
+#[test]
+fn test_ticker_aliases() {
+    let mut ticker_aliases =
+        TickerAliases::load(TICKER_ALIASES_PATH).expect("Error loading the ticker aliases");
+
+    // Nothing is turned down before the instrument lists are in
+    assert_eq!(Ok("POL"), ticker_aliases.resolve("MATIC"));
+    assert_eq!(Ok("NEW"), ticker_aliases.resolve("NEW"));
+
+    ticker_aliases.refresh(["POL", "BTC", "1000SATS", "MATIC2", "RNDR"].into_iter());
+    assert_eq!(Ok("POL"), ticker_aliases.resolve("MATIC"));
+    assert_eq!(Ok("BTC"), ticker_aliases.resolve("BTC"));
+    assert_eq!(Ok("SATS"), ticker_aliases.resolve("SATS"));
+    // A venue that hasn't switched to the new ticker yet still trades the old one
+    assert_eq!(Ok("RNDR"), ticker_aliases.resolve("RNDR"));
+    assert!(ticker_aliases.resolve("FTM").is_err());
+    assert!(ticker_aliases.resolve("NEW").is_err());
+
+    assert!(ticker_aliases::parse_aliases(r#"{"MATIC":"POL"}"#).is_ok());
+    assert!(ticker_aliases::parse_aliases(r#"{"MATIC":"MATIC"}"#).is_err());
+    assert!(ticker_aliases::parse_aliases(r#"{"MATIC":"POL","POL":"POL2"}"#).is_err());
+    assert!(ticker_aliases::parse_aliases(r#"{"MATIC":"pol"}"#).is_err());
+    assert!(ticker_aliases::parse_aliases(r#"["MATIC"]"#).is_err());
+}
+
+#[test]
+fn test_spot_filled_base_qty() {
+    let body = r#"{"symbol":"BTCUSDT","executedQty":"0.00200000","cummulativeQuoteQty":"100.00000000","fills":[{"price":"50000.00000000","qty":"0.00150000","commission":"0.00000150","commissionAsset":"BTC","tradeId":1},{"price":"50000.00000000","qty":"0.00050000","commission":"0.00000050","commissionAsset":"BTC","tradeId":2}]}"#;
+    let order_response: SpotOrderResponse =
+        serde_json::from_str(body).expect("Error parsing spot order response");
+
+    assert_eq!(
+        Decimal::from("0.001998"),
+        spot_filled_base_qty(&order_response, "BTC")
+    );
+
+    let body_bnb_fees = r#"{"symbol":"BTCUSDT","executedQty":"0.00200000","cummulativeQuoteQty":"100.00000000","fills":[{"price":"50000.00000000","qty":"0.00200000","commission":"0.00010000","commissionAsset":"BNB","tradeId":1}]}"#;
+    let order_response: SpotOrderResponse =
+        serde_json::from_str(body_bnb_fees).expect("Error parsing spot order response");
+
+    assert_eq!(
+        Decimal::from("0.002"),
+        spot_filled_base_qty(&order_response, "BTC")
+    );
+}
+
+#[test]
+fn test_order_ack() {
+    let futures_body = r#"{"orderId":22542179,"symbol":"BTCUSDT","status":"NEW","origQty":"10","executedQty":"0","type":"MARKET","reduceOnly":true,"side":"SELL"}"#;
+    let order_ack: OrderAck = serde_json::from_str(futures_body).expect("Error parsing order ack");
+    assert!(order_ack.accepted());
+
+    let expired_body = r#"{"symbol":"BTCUSDT","orderId":28,"status":"EXPIRED","executedQty":"0.00000000","fills":[]}"#;
+    let order_ack: OrderAck = serde_json::from_str(expired_body).expect("Error parsing order ack");
+    assert!(!order_ack.accepted());
+
+    // Rejections carry no order, so they never read as one
+    let rejected_body = r#"{"code":-2022,"msg":"ReduceOnly Order is rejected."}"#;
+    assert!(serde_json::from_str::<OrderAck>(rejected_body).is_err());
+    let filter_body = r#"{"code":-1013,"msg":"Filter failure: LOT_SIZE"}"#;
+    assert!(serde_json::from_str::<OrderAck>(filter_body).is_err());
+}
+
 #[tokio::test]
 async fn test_get_price() -> Result<(), Box<dyn error::Error>> {
     let client = Client::new();
     let trade_pair = "BTCUSDT";
 
-    let price = get_price(client.clone(), trade_pair).await?;
+    let price = get_price(client.clone(), trade_pair, "futures").await?;
 
-    assert_ne!(price, 0.0);
+    assert_ne!(price, Decimal::from(0));
 
     let trade_pair_empty = "";
 
-    let price_empty = get_price(client.clone(), trade_pair_empty).await?;
+    let price_empty = get_price(client.clone(), trade_pair_empty, "futures").await?;
 
-    assert_eq!(price_empty, 0.0);
+    assert_eq!(price_empty, Decimal::from(0));
 
     let trade_pair_invalid = "INVALID";
 
-    let price_invalid = get_price(client.clone(), trade_pair_invalid).await?;
+    let price_invalid = get_price(client.clone(), trade_pair_invalid, "futures").await?;
+
+    assert_eq!(price_invalid, Decimal::from(0));
 
-    assert_eq!(price_invalid, 0.0);
+    let price_spot = get_price(client.clone(), trade_pair, "spot").await?;
+
+    assert_ne!(price_spot, Decimal::from(0));
 
     Ok(())
 }
@@ -213,23 +741,740 @@ async fn test_get_leverage() -> Result<(), Box<dyn error::Error>> {
     let trade_pair = "BTCUSDT";
     let recv_window = "5000";
 
-    let leverage = get_trade_pair_leverage(client.clone(), trade_pair, recv_window).await?;
+    let position_risk = get_position_risk(client.clone(), trade_pair, recv_window).await?;
+    let leverage: Decimal = position_risk.leverage.parse().unwrap_or_default();
 
-    assert_ne!(leverage, 0.0);
+    assert_ne!(leverage, Decimal::from(0));
+    assert!(MarginMode::from_margin_type(&position_risk.marginType).is_some());
 
     let trade_pair_empty = "";
 
-    let leverage_empty =
-        get_trade_pair_leverage(client.clone(), trade_pair_empty, recv_window).await?;
+    let position_risk_empty =
+        get_position_risk(client.clone(), trade_pair_empty, recv_window).await?;
+    let leverage_empty: Decimal = position_risk_empty.leverage.parse().unwrap_or_default();
 
-    assert_eq!(leverage_empty, 20.0);
+    assert_eq!(leverage_empty, Decimal::from(20));
 
     let trade_pair_invalid = "INVALID";
 
-    let leverage_invalid =
-        get_trade_pair_leverage(client.clone(), trade_pair_invalid, recv_window).await?;
+    let position_risk_invalid =
+        get_position_risk(client.clone(), trade_pair_invalid, recv_window).await?;
+    let leverage_invalid: Decimal = position_risk_invalid.leverage.parse().unwrap_or_default();
 
-    assert_eq!(leverage_invalid, 0.0);
+    assert_eq!(leverage_invalid, Decimal::from(0));
 
     Ok(())
 }
+
+#[test]
+fn test_futures_setup_verify() {
+    let futures_setup = FuturesSetup {
+        leverage: 10,
+        margin_mode: MarginMode::Isolated,
+    };
+
+    assert_eq!(
+        Some(MarginMode::Isolated),
+        MarginMode::from_margin_type("isolated")
+    );
+    assert_eq!(
+        Some(MarginMode::Cross),
+        MarginMode::from_margin_type("cross")
+    );
+    assert_eq!(None, MarginMode::from_margin_type(""));
+
+    assert!(futures_setup
+        .verify(Decimal::from(10), Some(MarginMode::Isolated))
+        .is_ok());
+    assert!(futures_setup
+        .verify(Decimal::from(20), Some(MarginMode::Isolated))
+        .is_err());
+    assert!(futures_setup
+        .verify(Decimal::from(10), Some(MarginMode::Cross))
+        .is_err());
+    assert!(futures_setup.verify(Decimal::from(10), None).is_err());
+}
+
+#[test]
+fn test_exit_ladder() {
+    let tp = |time: u64, pct: &str, basis: TpBasis| TpInstance {
+        time,
+        pct: Decimal::from(pct),
+        basis,
+        price_target: None,
+    };
+
+    let ladder = ExitLadder::new(vec![
+        tp(10, "0.5", TpBasis::Original),
+        tp(20, "0.5", TpBasis::Remaining),
+        tp(30, "1", TpBasis::Remaining),
+    ])
+    .expect("Valid ladder");
+    assert_eq!(3, ladder.steps().len());
+    assert_eq!(
+        Decimal::from(50),
+        ladder.steps()[0].qty(Decimal::from(100), Decimal::from(100))
+    );
+    assert_eq!(
+        Decimal::from(25),
+        ladder.steps()[1].qty(Decimal::from(100), Decimal::from(50))
+    );
+
+    // Empty, short of 100%, over 100% and going back in time
+    assert!(ExitLadder::new(vec![]).is_err());
+    assert!(ExitLadder::new(vec![
+        tp(10, "0.5", TpBasis::Original),
+        tp(20, "0.25", TpBasis::Original),
+    ])
+    .is_err());
+    assert!(ExitLadder::new(vec![
+        tp(10, "0.75", TpBasis::Original),
+        tp(20, "0.5", TpBasis::Original),
+    ])
+    .is_err());
+    assert!(ExitLadder::new(vec![
+        tp(20, "0.5", TpBasis::Original),
+        tp(10, "0.5", TpBasis::Original),
+    ])
+    .is_err());
+    assert!(ExitLadder::new(vec![tp(10, "0", TpBasis::Original)]).is_err());
+}
+
+#[test]
+fn test_exit_triggers() {
+    let ladder = ExitLadder::new(vec![TpInstance {
+        time: 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Original,
+        price_target: Some(Decimal::from("0.3")),
+    }])
+    .and_then(|ladder| ladder.with_stop_loss(Decimal::from("0.1")))
+    .expect("Valid ladder");
+    assert!(ladder.watches_price());
+
+    let entry_price = Decimal::from(10);
+    let target_price = ladder.steps()[0].target_price(entry_price, Side::Long);
+    let stop_price = ladder.stop_price(entry_price);
+    assert_eq!(Some(Decimal::from(13)), target_price);
+    assert_eq!(Some(Decimal::from(9)), stop_price);
+
+    assert_eq!(
+        None,
+        price_trigger(Decimal::from(11), target_price, stop_price, Side::Long)
+    );
+    assert_eq!(
+        Some(ExitTrigger::PriceTarget),
+        price_trigger(Decimal::from(13), target_price, stop_price, Side::Long)
+    );
+    assert_eq!(
+        Some(ExitTrigger::StopLoss),
+        price_trigger(Decimal::from("8.5"), target_price, stop_price, Side::Long)
+    );
+    assert_eq!(
+        None,
+        price_trigger(Decimal::from(1), None, None, Side::Long)
+    );
+
+    assert!(ExitLadder::new(ladder.steps().to_vec())
+        .and_then(|ladder| ladder.with_stop_loss(Decimal::from(1)))
+        .is_err());
+    assert!(ExitLadder::new(vec![TpInstance {
+        time: 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Original,
+        price_target: Some(Decimal::from(0)),
+    }])
+    .is_err());
+}
+
+#[test]
+fn test_trailing_stop() {
+    let mut trailing_stop = TrailingStop::new(
+        Retrace::Pct(Decimal::from("0.1")),
+        Decimal::from(10),
+        Side::Long,
+    );
+    assert!(!trailing_stop.update(Decimal::from(12), 1_000));
+    assert!(!trailing_stop.update(Decimal::from(20), 2_000));
+    assert_eq!(Decimal::from(20), trailing_stop.peak());
+    assert_eq!(Some(Decimal::from(18)), trailing_stop.stop_price());
+    assert!(!trailing_stop.update(Decimal::from("18.5"), 3_000));
+    assert!(trailing_stop.update(Decimal::from(18), 4_000));
+
+    // Two 1 second bars with a range of 1 close before the ATR is ready
+    let mut trailing_stop = TrailingStop::new(
+        Retrace::Atr {
+            multiple: Decimal::from(2),
+            bar_secs: 1,
+            period: 2,
+        },
+        Decimal::from(10),
+        Side::Long,
+    );
+    assert!(!trailing_stop.update(Decimal::from(10), 0));
+    assert!(!trailing_stop.update(Decimal::from(11), 500));
+    assert_eq!(None, trailing_stop.atr());
+    assert!(!trailing_stop.update(Decimal::from(11), 1_000));
+    assert!(!trailing_stop.update(Decimal::from(10), 1_500));
+    assert_eq!(None, trailing_stop.atr());
+    assert!(!trailing_stop.update(Decimal::from(10), 2_000));
+    assert_eq!(Some(Decimal::from(1)), trailing_stop.atr());
+    assert_eq!(Some(Decimal::from(9)), trailing_stop.stop_price());
+    assert!(trailing_stop.update(Decimal::from(9), 2_500));
+
+    assert!(Retrace::Pct(Decimal::from(0)).validate().is_err());
+    assert!(Retrace::Atr {
+        multiple: Decimal::from(2),
+        bar_secs: 0,
+        period: 14,
+    }
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn test_protection() {
+    let tp = |time: u64, pct: &str, price_target: Option<&str>| TpInstance {
+        time,
+        pct: Decimal::from(pct),
+        basis: TpBasis::Original,
+        price_target: price_target.map(Decimal::from),
+    };
+    let ladder = ExitLadder::new(vec![
+        tp(60, "0.5", Some("0.5")),
+        tp(120, "0.25", Some("0.2")),
+        tp(180, "0.25", None),
+    ])
+    .and_then(|ladder| ladder.with_stop_loss(Decimal::from("0.1")))
+    .and_then(|ladder| ladder.with_trailing_stop(Retrace::Pct(Decimal::from("0.2"))))
+    .expect("Valid ladder");
+    let entry_price = Decimal::from(10);
+
+    let protection = ladder.protection(entry_price, 0, None);
+    assert_eq!(Some(Decimal::from(9)), protection.stop_price);
+    assert_eq!(Some(Decimal::from(15)), protection.target_price);
+
+    // The trailing stop only takes over once it is above the hard stop
+    let mut trailing_stop = ladder.trailing_stop(entry_price).expect("Trailing stop");
+    let protection = ladder.protection(entry_price, 1, Some(&trailing_stop));
+    assert_eq!(Some(Decimal::from(9)), protection.stop_price);
+    assert_eq!(Some(Decimal::from(12)), protection.target_price);
+
+    trailing_stop.update(Decimal::from(20), 1_000);
+    let protection = ladder.protection(entry_price, 2, Some(&trailing_stop));
+    assert_eq!(Some(Decimal::from(16)), protection.stop_price);
+    assert_eq!(None, protection.target_price);
+}
+
+#[test]
+fn test_short_exits() {
+    let ladder = ExitLadder::new(vec![
+        TpInstance {
+            time: 60,
+            pct: Decimal::from("0.5"),
+            basis: TpBasis::Original,
+            price_target: Some(Decimal::from("0.2")),
+        },
+        TpInstance {
+            time: 120,
+            pct: Decimal::from("0.5"),
+            basis: TpBasis::Original,
+            price_target: Some(Decimal::from("0.5")),
+        },
+    ])
+    .and_then(|ladder| ladder.with_stop_loss(Decimal::from("0.1")))
+    .and_then(|ladder| ladder.with_trailing_stop(Retrace::Pct(Decimal::from("0.2"))))
+    .expect("Valid ladder")
+    .with_side(Side::Short);
+    let entry_price = Decimal::from(10);
+
+    // Targets are below the entry and the stop above it
+    let target_price = ladder.steps()[0].target_price(entry_price, Side::Short);
+    let stop_price = ladder.stop_price(entry_price);
+    assert_eq!(Some(Decimal::from(8)), target_price);
+    assert_eq!(Some(Decimal::from(11)), stop_price);
+    assert_eq!(
+        None,
+        price_trigger(Decimal::from(9), target_price, stop_price, Side::Short)
+    );
+    assert_eq!(
+        Some(ExitTrigger::PriceTarget),
+        price_trigger(Decimal::from("7.5"), target_price, stop_price, Side::Short)
+    );
+    assert_eq!(
+        Some(ExitTrigger::StopLoss),
+        price_trigger(Decimal::from(12), target_price, stop_price, Side::Short)
+    );
+
+    // The trailing stop follows the low and fires on a bounce
+    let mut trailing_stop = ladder.trailing_stop(entry_price).expect("Trailing stop");
+    assert!(!trailing_stop.update(Decimal::from(5), 1_000));
+    assert_eq!(Decimal::from(5), trailing_stop.peak());
+    assert_eq!(Some(Decimal::from(6)), trailing_stop.stop_price());
+    assert!(!trailing_stop.update(Decimal::from("5.5"), 2_000));
+    assert!(trailing_stop.update(Decimal::from(6), 3_000));
+
+    let protection = ladder.protection(entry_price, 0, Some(&trailing_stop));
+    assert_eq!(Some(Decimal::from(6)), protection.stop_price);
+    assert_eq!(Some(Decimal::from(5)), protection.target_price);
+    assert_eq!(Side::Short, protection.side);
+
+    assert_eq!(Decimal::from(3), Side::Short.held(Decimal::from(-3)));
+    assert_eq!(Decimal::from(-3), Side::Long.held(Decimal::from(-3)));
+}
+
+#[test]
+fn test_exit_schedule() {
+    let path = env::temp_dir().join("test_exit_schedule.json");
+    let path = path.to_str().expect("Temp path");
+    let _ = std::fs::remove_file(path);
+    assert!(exit_schedule::load_exits(path).is_empty());
+
+    let ladder = ExitLadder::new(vec![
+        TpInstance {
+            time: 2 * 60,
+            pct: Decimal::from("0.75"),
+            basis: TpBasis::Original,
+            price_target: Some(Decimal::from("0.3")),
+        },
+        TpInstance {
+            time: 13 * 60,
+            pct: Decimal::from("1"),
+            basis: TpBasis::Remaining,
+            price_target: None,
+        },
+    ])
+    .and_then(|ladder| ladder.with_stop_loss(Decimal::from("0.1")))
+    .and_then(|ladder| {
+        ladder.with_trailing_stop(Retrace::Atr {
+            multiple: Decimal::from(3),
+            bar_secs: 5,
+            period: 12,
+        })
+    })
+    .expect("Valid ladder");
+    let entry = Entry {
+        qty: Decimal::from("12.5"),
+        price: Decimal::from("0.0421"),
+    };
+    let mut pending_exit = PendingExit::new("ABCUSDT", "futures", entry, &ladder, 1_000_000);
+    assert_eq!(vec![1_120_000, 1_780_000], pending_exit.due_ms);
+
+    exit_schedule::save_exit(path, &pending_exit).expect("Saved");
+    exit_schedule::save_exit(
+        path,
+        &PendingExit::new("ABCUSDT", "spot", entry, &ladder, 1_000_000),
+    )
+    .expect("Saved");
+    // Saving the same leg again replaces it
+    pending_exit.next_step = 1;
+    pending_exit.remaining_qty = Decimal::from("3.125");
+    exit_schedule::save_exit(path, &pending_exit).expect("Saved");
+
+    let pending_exits = exit_schedule::load_exits(path);
+    assert_eq!(2, pending_exits.len());
+    let loaded = pending_exits
+        .iter()
+        .find(|pending_exit| pending_exit.category == "futures")
+        .expect("Futures exit");
+    assert_eq!(1, loaded.next_step);
+    assert_eq!(Decimal::from("3.125"), loaded.remaining_qty);
+    assert_eq!(Decimal::from("0.0421"), loaded.entry_price);
+    assert_eq!(2, loaded.exit_ladder.steps().len());
+    assert_eq!(
+        Some(Decimal::from("0.3")),
+        loaded.exit_ladder.steps()[0].price_target
+    );
+    assert_eq!(
+        ladder.protection(entry.price, 0, None),
+        loaded.exit_ladder.protection(entry.price, 0, None)
+    );
+
+    exit_schedule::remove_exit(path, &pending_exit).expect("Removed");
+    let pending_exits = exit_schedule::load_exits(path);
+    assert_eq!(1, pending_exits.len());
+    assert_eq!("spot", pending_exits[0].category);
+
+    exit_schedule::clear_exits(path).expect("Cleared");
+    assert!(exit_schedule::load_exits(path).is_empty());
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_scheduled_buys() {
+    let path = env::temp_dir().join("test_scheduled_buys.json");
+    let path = path.to_str().expect("Temp path");
+    let _ = std::fs::remove_file(path);
+    assert!(exit_schedule::load_scheduled_buys(path).is_empty());
+
+    let ladder = ExitLadder::new(vec![TpInstance {
+        time: 5 * 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Remaining,
+        price_target: None,
+    }])
+    .expect("Valid ladder");
+    let mut scheduled_buy = ScheduledBuy {
+        ticker: "ABC".to_string(),
+        unit_coin_qty: Decimal::from("25"),
+        listing_time_ms: 1_700_000_000_000,
+        exit_ladder: ladder.clone(),
+    };
+    exit_schedule::save_scheduled_buy(path, &scheduled_buy).expect("Saved");
+    exit_schedule::save_scheduled_buy(
+        path,
+        &ScheduledBuy {
+            ticker: "XYZ".to_string(),
+            ..scheduled_buy.clone()
+        },
+    )
+    .expect("Saved");
+    // Scheduling the same ticker again replaces it
+    scheduled_buy.unit_coin_qty = Decimal::from("40");
+    exit_schedule::save_scheduled_buy(path, &scheduled_buy).expect("Saved");
+
+    let scheduled_buys = exit_schedule::load_scheduled_buys(path);
+    assert_eq!(2, scheduled_buys.len());
+    let loaded = scheduled_buys
+        .iter()
+        .find(|scheduled_buy| scheduled_buy.ticker == "ABC")
+        .expect("ABC buy");
+    assert_eq!(Decimal::from("40"), loaded.unit_coin_qty);
+    assert_eq!(1_700_000_000_000, loaded.listing_time_ms);
+    assert_eq!(1, loaded.exit_ladder.steps().len());
+
+    exit_schedule::remove_scheduled_buy(path, "ABC").expect("Removed");
+    let scheduled_buys = exit_schedule::load_scheduled_buys(path);
+    assert_eq!(1, scheduled_buys.len());
+    assert_eq!("XYZ", scheduled_buys[0].ticker);
+
+    exit_schedule::clear_scheduled_buys(path).expect("Cleared");
+    assert!(exit_schedule::load_scheduled_buys(path).is_empty());
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_leg_claim() {
+    let claim = LegClaim::claim("CLAIMUSDT", "futures").expect("Claimed");
+    assert!(LegClaim::claim("CLAIMUSDT", "futures").is_err());
+    // The other market of the same symbol is a separate leg
+    let spot_claim = LegClaim::claim("CLAIMUSDT", "spot").expect("Claimed");
+    drop(claim);
+    drop(LegClaim::claim("CLAIMUSDT", "futures").expect("Released"));
+    drop(spot_claim);
+}
+
+#[test]
+fn test_find_orphans() {
+    let ladder = ExitLadder::new(vec![TpInstance {
+        time: 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Original,
+        price_target: None,
+    }])
+    .expect("Valid ladder");
+    let entry = Entry {
+        qty: Decimal::from(1),
+        price: Decimal::from(1),
+    };
+    let pending_exits = vec![PendingExit::new("ABCUSDT", "futures", entry, &ladder, 0)];
+    let holding = |symbol: &str, category: &str, qty: i64| Holding {
+        symbol: symbol.to_string(),
+        category: category.to_string(),
+        qty: Decimal::from(qty),
+        entry_price: Decimal::from(0),
+    };
+
+    let orphans = reconcile::find_orphans(
+        vec![
+            holding("ABCUSDT", "futures", 10),
+            holding("ABCUSDT", "spot", 10),
+            holding("XYZUSDT", "futures", -5),
+            holding("DEFUSDT", "futures", 0),
+        ],
+        &pending_exits,
+    );
+    assert_eq!(
+        vec![
+            holding("ABCUSDT", "spot", 10),
+            holding("XYZUSDT", "futures", -5)
+        ],
+        orphans
+    );
+
+    let orphan_orders = reconcile::find_orphan_orders(
+        vec![
+            ("XYZUSDT".to_string(), "spot".to_string()),
+            ("ABCUSDT".to_string(), "futures".to_string()),
+            ("XYZUSDT".to_string(), "spot".to_string()),
+        ],
+        &pending_exits,
+    );
+    assert_eq!(
+        vec![("XYZUSDT".to_string(), "spot".to_string())],
+        orphan_orders
+    );
+
+    assert_eq!(Some(OrphanPolicy::Adopt), OrphanPolicy::from_arg("Adopt"));
+    assert_eq!(None, OrphanPolicy::from_arg("close"));
+}
+
+#[test]
+fn test_shutdown_policy() {
+    assert_eq!(
+        Some(ShutdownPolicy::Flatten),
+        ShutdownPolicy::from_arg("flatten")
+    );
+    assert_eq!(
+        Some(ShutdownPolicy::Persist),
+        ShutdownPolicy::from_arg("Persist")
+    );
+    assert_eq!(
+        Some(ShutdownPolicy::Wait(Duration::from_secs(900))),
+        ShutdownPolicy::from_arg("wait")
+    );
+    assert_eq!(
+        Some(ShutdownPolicy::Wait(Duration::from_secs(120))),
+        ShutdownPolicy::from_arg("wait:120")
+    );
+    assert_eq!(None, ShutdownPolicy::from_arg("wait:soon"));
+    assert_eq!(None, ShutdownPolicy::from_arg("flatten:10"));
+    assert_eq!(None, ShutdownPolicy::from_arg("exit"));
+}
+
+#[test]
+fn test_health() {
+    let now = tokio::time::Instant::now();
+    let start = now - Duration::from_secs(600);
+    assert!(health::is_healthy(now, now, None));
+    assert!(health::is_healthy(
+        now,
+        now - Duration::from_secs(30),
+        Some(now - Duration::from_secs(10))
+    ));
+    // A dead feed or a stuck order both stop the heartbeat
+    assert!(!health::is_healthy(now, start, None));
+    assert!(!health::is_healthy(now, now, Some(start)));
+
+    assert_eq!(None, health::busy_since());
+    let busy = Busy::start();
+    let since = health::busy_since().unwrap();
+    busy.idle();
+    assert_eq!(None, health::busy_since());
+    busy.resume();
+    assert!(health::busy_since().unwrap() >= since);
+    drop(busy);
+    assert_eq!(None, health::busy_since());
+}
+
+#[test]
+fn test_title_rules() {
+    let rule = |pattern: &str, venues: &str, steps: &str| {
+        format!(
+            r#"[{{"name":"binance_listing","contains":["Binance Will List"],"pattern":"{}",
+            "case":"BinanceListing","venues":{},"exit_ladder":{{"steps":{}}}}}]"#,
+            pattern, venues, steps
+        )
+    };
+    let steps = r#"[{"time":60,"pct":"1","basis":"Original"}]"#;
+    let rule_set = title_rules::parse_rules(
+        &rule(r"\\(([^()]+)\\)", r#"["futures"]"#, steps),
+        TITLE_RULE_VENUES,
+    )
+    .expect("Error parsing the title rules");
+    let title = "Binance Will List Dymension (DYM) with Seed Tag Applied";
+    let (title_rule, symbols) = rule_set.classify(title).expect("No rule matched");
+    assert_eq!("binance_listing", title_rule.name);
+    assert_eq!(vec!["DYM"], symbols);
+    assert!(title_rule.trades_on("futures"));
+    assert!(!title_rule.trades_on("spot"));
+    assert!(rule_set
+        .classify("Binance Will Delist Dymension (DYM)")
+        .is_none());
+
+    // Everything is checked at load, not when a headline comes in
+    assert!(title_rules::parse_rules(
+        &rule(r"\\(([^()]+\\)", r#"["futures"]"#, steps),
+        TITLE_RULE_VENUES
+    )
+    .is_err());
+    assert!(title_rules::parse_rules(
+        &rule(r"\\([^()]+\\)", r#"["futures"]"#, steps),
+        TITLE_RULE_VENUES
+    )
+    .is_err());
+    assert!(title_rules::parse_rules(
+        &rule(r"\\(([^()]+)\\)", r#"["margin"]"#, steps),
+        TITLE_RULE_VENUES
+    )
+    .is_err());
+    assert!(title_rules::parse_rules(
+        &rule(
+            r"\\(([^()]+)\\)",
+            r#"["futures"]"#,
+            r#"[{"time":60,"pct":"0.5","basis":"Original"}]"#
+        ),
+        TITLE_RULE_VENUES
+    )
+    .is_err());
+    assert!(
+        title_rules::parse_rules(r#"[{"name":"binance_listing"}]"#, TITLE_RULE_VENUES).is_err()
+    );
+    let short_spot = rule(r"\\(([^()]+)\\)", r#"["spot"],"side":"Short""#, steps);
+    assert!(title_rules::parse_rules(&short_spot, TITLE_RULE_VENUES).is_err());
+    let scheduled_futures = rule(
+        r"\\(([^()]+)\\)",
+        r#"["futures"],"spot_entry":"AtListingTime""#,
+        steps,
+    );
+    assert!(title_rules::parse_rules(&scheduled_futures, TITLE_RULE_VENUES).is_err());
+    let unknown_market = rule(r"\\(([^()]+)\\)", r#"["spot"],"market":"ETH""#, steps);
+    assert!(title_rules::parse_rules(&unknown_market, TITLE_RULE_VENUES).is_err());
+    let zero_size = rule(r"\\(([^()]+)\\)", r#"["spot"],"size_scale":"0""#, steps);
+    assert!(title_rules::parse_rules(&zero_size, TITLE_RULE_VENUES).is_err());
+    let half_size = rule(r"\\(([^()]+)\\)", r#"["spot"],"size_scale":"0.5""#, steps);
+    assert!(title_rules::parse_rules(&half_size, TITLE_RULE_VENUES).is_ok());
+
+    let setup = |venues: &str, futures_setup: &str| {
+        title_rules::parse_rules(
+            &rule(
+                r"\\(([^()]+)\\)",
+                &format!(r#"{},"futures_setup":{}"#, venues, futures_setup),
+                steps,
+            ),
+            TITLE_RULE_VENUES,
+        )
+    };
+    let cross = setup(r#"["futures"]"#, r#"{"leverage":10,"margin_mode":"Cross"}"#)
+        .expect("Error parsing the futures setup");
+    assert_eq!(10, cross.rules()[0].futures_setup.leverage);
+    assert_eq!(
+        MarginMode::Cross,
+        cross.rules()[0].futures_setup.margin_mode
+    );
+    assert_eq!(5, rule_set.rules()[0].futures_setup.leverage);
+    assert!(setup(
+        r#"["futures"]"#,
+        r#"{"leverage":0,"margin_mode":"Isolated"}"#
+    )
+    .is_err());
+    assert!(setup(
+        r#"["futures"]"#,
+        r#"{"leverage":500,"margin_mode":"Isolated"}"#
+    )
+    .is_err());
+    assert!(setup(
+        r#"["futures"]"#,
+        r#"{"leverage":10,"margin_mode":"Portfolio"}"#
+    )
+    .is_err());
+    assert!(setup(r#"["spot"]"#, r#"{"leverage":10,"margin_mode":"Isolated"}"#).is_err());
+}
+
+#[test]
+fn test_listing_time() {
+    let title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)
+        .expect("Error loading the title rules");
+    let body = "Binance will then list Ethena (ENA) at 2024-04-02 08:00 (UTC) and open trading \
+                with ENA/BTC, ENA/USDT, ENA/BNB, ENA/FDUSD and ENA/TRY trading pairs.";
+    assert_eq!(
+        Some("2024-04-02T08:00:00+00:00".to_string()),
+        title_rules.listing_time(body).map(|time| time.to_rfc3339())
+    );
+    // Launchpool notices give the farming start first
+    let launchpool =
+        "Users will be able to lock their BNB and FDUSD into separate pools to farm ENA \
+                      tokens over 4 days, with farming starting from 2024-03-30 00:00 (UTC). \
+                      Binance will then list ENA at 2024-04-02 08:00 (UTC) and open trading \
+                      with ENA/BTC, ENA/USDT, ENA/BNB, ENA/FDUSD and ENA/TRY trading pairs.";
+    assert_eq!(
+        Some("2024-04-02T08:00:00+00:00".to_string()),
+        title_rules
+            .listing_time(launchpool)
+            .map(|time| time.to_rfc3339())
+    );
+    assert!(title_rules
+        .listing_time("Farming starts at 2024-03-30 00:00 (UTC)")
+        .is_none());
+    assert!(title_rules
+        .listing_time("Introducing Ethena (ENA) on Binance Launchpool!")
+        .is_none());
+    assert!(title_rules
+        .listing_time("Binance Will List ENA at 2024-13-02 08:00 (UTC)")
+        .is_none());
+}
+
+#[test]
+fn test_rule_set() {
+    let rules = r#"[
+        {"name":"futures","contains":["Binance","Futures"],"pattern":"M (\\w+) Perpetual",
+        "case":"BinanceFuturesListing","venues":["futures"],
+        "exit_ladder":{"steps":[{"time":7,"pct":"1","basis":"Original"}]}},
+        {"name":"listing","contains":["Binance"],"pattern":"\\((\\w+)\\)",
+        "case":"BinanceListing","venues":["spot"],
+        "exit_ladder":{"steps":[{"time":60,"pct":"1","basis":"Original"}]}},
+        {"name":"warning","contains":["유의 종목 지정"],"excludes":["해제"],
+        "pattern":"\\((\\w+)\\)","case":"UpbitInvestmentWarning","venues":["futures"],
+        "side":"Short","exit_ladder":{"steps":[{"time":60,"pct":"1","basis":"Original"}]}}
+    ]"#;
+    let rule_set =
+        title_rules::parse_rules(rules, TITLE_RULE_VENUES).expect("Error parsing the title rules");
+
+    // A rule needs all of its texts, and earlier rules win
+    let (title_rule, symbols) = rule_set
+        .classify("Binance Futures Will Launch USDⓈ-M ZETA Perpetual Contract")
+        .expect("No rule matched");
+    assert_eq!("futures", title_rule.name);
+    assert_eq!(vec!["ZETA"], symbols);
+    let (title_rule, symbols) = rule_set
+        .classify("Binance Will List Dymension (DYM)")
+        .expect("No rule matched");
+    assert_eq!("listing", title_rule.name);
+    assert_eq!(vec!["DYM"], symbols);
+    assert!(rule_set.classify("Futures (DYM)").is_none());
+
+    // An excluded text rules the title out even with every text found
+    let (title_rule, symbols) = rule_set
+        .classify("유의 종목 지정 안내 (PUNDIX)")
+        .expect("No rule matched");
+    assert_eq!("warning", title_rule.name);
+    assert_eq!(vec!["PUNDIX"], symbols);
+    assert!(rule_set
+        .classify("유의 종목 지정 해제 안내 (PUNDIX)")
+        .is_none());
+
+    assert_eq!(
+        vec!["KRW", "BTC"],
+        rule_set.markets("KRW, BTC 마켓 디지털 자산 추가 (ALT, PYTH)")
+    );
+    assert!(rule_set
+        .markets("Binance Will List Dymension (DYM)")
+        .is_empty());
+
+    let empty_exclude = rules.replace(r#""excludes":["해제"]"#, r#""excludes":[""]"#);
+    assert!(title_rules::parse_rules(&empty_exclude, TITLE_RULE_VENUES).is_err());
+}
+
+// Title to symbols latency over the shipped rules. Run with
+// cargo test --release -- --ignored bench_process_title --nocapture
+#[test]
+#[ignore]
+fn bench_process_title() {
+    let title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)
+        .expect("Error loading the title rules");
+    let titles = [
+        "Binance Will List Dymension (DYM) with Seed Tag Applied",
+        "KRW, BTC 마켓 디지털 자산 추가 (ALT, PYTH)",
+        "Binance Futures Will Launch USDⓈ-M ZETA Perpetual Contract With Up to 50x Leverage",
+        "맨틀(MNT) 원화 마켓 추가",
+        "This is a random text",
+    ];
+    let iterations = 100_000;
+    for title in titles {
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(process_title(&title_rules, std::hint::black_box(title)));
+        }
+        println!("{:?} per title for {}", start.elapsed() / iterations, title);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::pre_trade;
+use crate::symbol_resolution::split_multiplier;
+
+use log::{error, info};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::SystemTime;
+
+pub const TICKER_ALIASES_PATH: &str = "ticker_aliases.json";
+
+// Checks the aliases file, an object of announced ticker to the asset the venues trade it as
+pub fn parse_aliases(json: &str) -> Result<HashMap<String, String>, String> {
+    let aliases: HashMap<String, String> =
+        serde_json::from_str(json).map_err(|e| format!("invalid aliases file: {}", e))?;
+    for (ticker, asset) in aliases.iter() {
+        pre_trade::check_ticker(ticker)
+            .and_then(|_| pre_trade::check_ticker(asset))
+            .map_err(|e| format!("alias {}: {}", ticker, e))?;
+        if ticker == asset {
+            return Err(format!("alias {} points to itself", ticker));
+        }
+        // One lookup has to land on the asset, so a rebrand of a rebrand is written out in full
+        if aliases.contains_key(asset) {
+            return Err(format!(
+                "alias {} points to {} which is an alias too",
+                ticker, asset
+            ));
+        }
+    }
+    Ok(aliases)
+}
+
+pub struct TickerAliases {
+    path: String,
+    modified: Option<SystemTime>,
+    aliases: HashMap<String, String>,
+    // Coins the venues list, without multiplier prefixes, as of the last instrument lists
+    listed: HashSet<String>,
+}
+
+impl TickerAliases {
+    pub fn load(path: &str) -> Result<TickerAliases, String> {
+        let modified = modified(path);
+        let json = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        Ok(TickerAliases {
+            path: path.to_string(),
+            modified,
+            aliases: parse_aliases(&json)?,
+            listed: HashSet::new(),
+        })
+    }
+
+    // Picks up edits to the file. A file that doesn't validate is logged and the aliases in use
+    // are kept
+    pub fn reload_if_changed(&mut self) {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match TickerAliases::load(&self.path) {
+            Ok(ticker_aliases) => {
+                info!(
+                    "Reloaded {} ticker aliases from {}",
+                    ticker_aliases.aliases.len(),
+                    self.path
+                );
+                self.aliases = ticker_aliases.aliases;
+            }
+            Err(e) => error!("Keeping the current ticker aliases: {}", e),
+        }
+    }
+
+    // Replaces what the venues list with the base assets of fresh instrument lists
+    pub fn refresh<'a>(&mut self, base_assets: impl Iterator<Item = &'a str>) {
+        self.listed = base_assets
+            .map(|asset| split_multiplier(asset).0.to_string())
+            .collect();
+    }
+
+    // The asset a ticker trades as, or why no venue can trade it. The old ticker of a rebrand
+    // still counts while the venues haven't switched, and until the instrument lists are in every
+    // ticker is taken as is
+    pub fn resolve<'a>(&'a self, ticker: &'a str) -> Result<&'a str, String> {
+        let asset = self.aliases.get(ticker).map_or(ticker, String::as_str);
+        if self.listed.is_empty() || self.is_listed(asset) {
+            return Ok(asset);
+        }
+        if self.is_listed(ticker) {
+            return Ok(ticker);
+        }
+        if asset != ticker {
+            return Err(format!("no venue lists {} or its asset {}", ticker, asset));
+        }
+        Err(format!("no venue lists {} and it has no alias", ticker))
+    }
+
+    fn is_listed(&self, ticker: &str) -> bool {
+        self.listed.contains(split_multiplier(ticker).0)
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,348 @@
+use crate::decimal_string;
+use crate::exit_ladder::{ExitLadder, Side};
+use crate::futures_setup::{FuturesSetup, DEFAULT_FUTURES_SETUP};
+use crate::TpCases;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use fancy_regex::Regex;
+use fraction::Decimal;
+use log::{error, info};
+use regex::RegexSet;
+use serde::Deserialize;
+use std::fs;
+use std::time::SystemTime;
+
+pub const TITLE_RULES_PATH: &str = "title_rules.json";
+
+// Markets an Upbit notice can add a coin to, the one that moves the price most first
+pub const MARKETS: &[&str] = &["KRW", "BTC", "USDT"];
+
+// When the spot leg of a rule buys
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Deserialize)]
+pub enum SpotEntry {
+    #[default]
+    Now,
+    // The coin isn't tradable yet, the buy waits for the listing time given in the announcement
+    AtListingTime,
+}
+
+// One entry of the rules file, as written
+#[derive(Deserialize)]
+struct RuleConfig {
+    name: String,
+    // Every one of these has to be in the title for the rule to apply
+    contains: Vec<String>,
+    // And none of these, for notices that read the same but mean the opposite
+    #[serde(default)]
+    excludes: Vec<String>,
+    // The first capture group of every match is a ticker
+    pattern: String,
+    case: TpCases,
+    venues: Vec<String>,
+    // Shorts open on futures only
+    #[serde(default)]
+    side: Side,
+    #[serde(default)]
+    spot_entry: SpotEntry,
+    // Only for notices whose first market is this one, so a KRW listing and a BTC-only listing
+    // can trade differently
+    #[serde(default)]
+    market: Option<String>,
+    // Scales the position sizes given on the command line
+    #[serde(default, with = "decimal_string::option")]
+    size_scale: Option<Decimal>,
+    // Leverage and margin mode of the futures leg
+    #[serde(default)]
+    futures_setup: Option<FuturesSetup>,
+    exit_ladder: ExitLadder,
+}
+
+// A validated rule with its pattern compiled
+pub struct TitleRule {
+    pub name: String,
+    contains: Vec<String>,
+    excludes: Vec<String>,
+    regex: Regex,
+    market: Option<String>,
+    pub case: TpCases,
+    pub venues: Vec<String>,
+    pub spot_entry: SpotEntry,
+    pub size_scale: Decimal,
+    pub futures_setup: FuturesSetup,
+    pub exit_ladder: ExitLadder,
+}
+
+impl TitleRule {
+    pub fn symbols<'a>(&self, title: &'a str) -> Vec<&'a str> {
+        self.regex
+            .captures_iter(title)
+            .flatten()
+            .filter_map(|captures| captures.get(1))
+            .map(|m| m.as_str())
+            // A market named next to the coins is never one of them
+            .filter(|symbol| !MARKETS.contains(symbol))
+            .collect()
+    }
+
+    pub fn trades_on(&self, venue: &str) -> bool {
+        self.venues.iter().any(|v| v == venue)
+    }
+}
+
+// Every rule compiled into one matcher. The set finds which of the rules' texts are in the title
+// in a single pass, and the first rule with all of its texts found and none of its excluded ones
+// is the match
+pub struct RuleSet {
+    rules: Vec<TitleRule>,
+    texts: RegexSet,
+    // Indexes into `texts` of what each rule needs and what rules it out
+    conditions: Vec<(Vec<usize>, Vec<usize>)>,
+    markets: Regex,
+    listing_time: Regex,
+}
+
+impl RuleSet {
+    fn new(rules: Vec<TitleRule>) -> Result<RuleSet, String> {
+        let mut texts: Vec<&str> = Vec::new();
+        let conditions = rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.contains
+                        .iter()
+                        .map(|text| index_of(&mut texts, text))
+                        .collect(),
+                    rule.excludes
+                        .iter()
+                        .map(|text| index_of(&mut texts, text))
+                        .collect(),
+                )
+            })
+            .collect();
+        let texts = RegexSet::new(texts.iter().map(|text| regex::escape(text)))
+            .map_err(|e| format!("rule texts don't compile: {}", e))?;
+        let markets = Regex::new(&format!("((?:{0})(?:, (?:{0}))*) 마켓", MARKETS.join("|")))
+            .map_err(|e| format!("market pattern doesn't compile: {}", e))?;
+        // The first time after the word "list" with no other time in between, so a farming or
+        // deposit time earlier in the text isn't taken for the listing
+        let listing_time = Regex::new(
+            r"(?i)\blist\b(?:(?!\(UTC\)).)*? at (\d{4}-\d{2}-\d{2} \d{2}:\d{2}) \(UTC\)",
+        )
+        .map_err(|e| format!("listing time pattern doesn't compile: {}", e))?;
+        Ok(RuleSet {
+            rules,
+            texts,
+            conditions,
+            markets,
+            listing_time,
+        })
+    }
+
+    // Rules are tried in file order, the first one that matches wins along with its tickers
+    pub fn classify<'a>(&self, title: &'a str) -> Option<(&TitleRule, Vec<&'a str>)> {
+        let found = self.texts.matches(title);
+        if !found.matched_any() {
+            return None;
+        }
+        let markets = self.markets(title);
+        let first_market = MARKETS.iter().find(|market| markets.contains(market));
+        self.rules
+            .iter()
+            .zip(self.conditions.iter())
+            .find(|(rule, (contains, excludes))| {
+                contains.iter().all(|&index| found.matched(index))
+                    && !excludes.iter().any(|&index| found.matched(index))
+                    && rule
+                        .market
+                        .as_ref()
+                        .is_none_or(|market| first_market.is_some_and(|first| first == market))
+            })
+            .map(|(rule, _)| (rule, rule.symbols(title)))
+    }
+
+    // The markets a notice adds its coins to, as in "KRW, BTC 마켓 디지털 자산 추가"
+    pub fn markets<'a>(&self, title: &'a str) -> Vec<&'a str> {
+        match self.markets.captures(title) {
+            Ok(Some(captures)) => captures
+                .get(1)
+                .map(|markets| markets.as_str().split(", ").collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Listing time of an announcement, written as "will list ... at 2024-04-02 08:00 (UTC)"
+    pub fn listing_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let captures = self.listing_time.captures(text).ok()??;
+        NaiveDateTime::parse_from_str(captures.get(1)?.as_str(), "%Y-%m-%d %H:%M")
+            .ok()
+            .map(|time| time.and_utc())
+    }
+
+    pub fn rules(&self) -> &[TitleRule] {
+        &self.rules
+    }
+}
+
+// Position of the text in the set, added if it isn't there yet
+fn index_of<'a>(texts: &mut Vec<&'a str>, text: &'a str) -> usize {
+    match texts.iter().position(|t| *t == text) {
+        Some(index) => index,
+        None => {
+            texts.push(text);
+            texts.len() - 1
+        }
+    }
+}
+
+// Checks and compiles every rule, so a bad file is rejected whole instead of failing on a headline
+pub fn parse_rules(json: &str, venues: &[&str]) -> Result<RuleSet, String> {
+    let configs: Vec<RuleConfig> =
+        serde_json::from_str(json).map_err(|e| format!("invalid rules file: {}", e))?;
+    let mut rules: Vec<TitleRule> = Vec::new();
+    for config in configs {
+        let name = config.name;
+        if name.is_empty() {
+            return Err("a rule has no name".to_string());
+        }
+        if rules.iter().any(|rule| rule.name == name) {
+            return Err(format!("rule {} is defined twice", name));
+        }
+        if config.contains.is_empty() || config.contains.iter().any(String::is_empty) {
+            return Err(format!("rule {} would match every title", name));
+        }
+        if config.excludes.iter().any(String::is_empty) {
+            return Err(format!("rule {} would match no title", name));
+        }
+        let regex = Regex::new(&config.pattern)
+            .map_err(|e| format!("rule {} pattern doesn't compile: {}", name, e))?;
+        if regex.captures_len() < 2 {
+            return Err(format!("rule {} pattern has no capture group", name));
+        }
+        if config.venues.is_empty() {
+            return Err(format!("rule {} has no venues", name));
+        }
+        if let Some(venue) = config
+            .venues
+            .iter()
+            .find(|venue| !venues.contains(&venue.as_str()))
+        {
+            return Err(format!(
+                "rule {} venue {} is not one of {:?}",
+                name, venue, venues
+            ));
+        }
+        if config.side == Side::Short && config.venues.iter().any(|venue| venue == "spot") {
+            return Err(format!("rule {} can't short spot", name));
+        }
+        if config.spot_entry == SpotEntry::AtListingTime
+            && !config.venues.iter().any(|venue| venue == "spot")
+        {
+            return Err(format!("rule {} schedules a spot buy without spot", name));
+        }
+        if let Some(market) = config
+            .market
+            .as_ref()
+            .filter(|market| !MARKETS.contains(&market.as_str()))
+        {
+            return Err(format!(
+                "rule {} market {} is not one of {:?}",
+                name, market, MARKETS
+            ));
+        }
+        let size_scale = config.size_scale.unwrap_or(Decimal::from(1));
+        if !size_scale.is_finite() || size_scale <= Decimal::from(0) {
+            return Err(format!(
+                "rule {} size scale {} is not positive",
+                name, size_scale
+            ));
+        }
+        let futures_setup = match config.futures_setup {
+            Some(_) if config.venues.iter().all(|venue| venue == "spot") => {
+                return Err(format!("rule {} sets up futures without futures", name));
+            }
+            Some(futures_setup) => futures_setup
+                .validated()
+                .map_err(|e| format!("rule {} futures setup: {}", name, e))?,
+            None => DEFAULT_FUTURES_SETUP,
+        };
+        let exit_ladder = config
+            .exit_ladder
+            .validated()
+            .map_err(|e| format!("rule {} exit ladder: {}", name, e))?
+            .with_side(config.side);
+        rules.push(TitleRule {
+            name,
+            contains: config.contains,
+            excludes: config.excludes,
+            regex,
+            market: config.market,
+            case: config.case,
+            venues: config.venues,
+            spot_entry: config.spot_entry,
+            size_scale,
+            futures_setup,
+            exit_ladder,
+        });
+    }
+    RuleSet::new(rules)
+}
+
+pub struct TitleRules {
+    path: String,
+    venues: Vec<&'static str>,
+    modified: Option<SystemTime>,
+    rule_set: RuleSet,
+}
+
+impl TitleRules {
+    pub fn load(path: &str, venues: &[&'static str]) -> Result<TitleRules, String> {
+        let modified = modified(path);
+        let json = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        Ok(TitleRules {
+            path: path.to_string(),
+            venues: venues.to_vec(),
+            modified,
+            rule_set: parse_rules(&json, venues)?,
+        })
+    }
+
+    // Picks up edits to the file. A file that doesn't validate is logged and the rules in use
+    // are kept
+    pub fn reload_if_changed(&mut self) {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match TitleRules::load(&self.path, &self.venues) {
+            Ok(title_rules) => {
+                info!(
+                    "Reloaded {} title rules from {}",
+                    title_rules.rules().len(),
+                    self.path
+                );
+                self.rule_set = title_rules.rule_set;
+            }
+            Err(e) => error!("Keeping the current title rules: {}", e),
+        }
+    }
+
+    pub fn classify<'a>(&self, title: &'a str) -> Option<(&TitleRule, Vec<&'a str>)> {
+        self.rule_set.classify(title)
+    }
+
+    pub fn listing_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        self.rule_set.listing_time(text)
+    }
+
+    pub fn rules(&self) -> &[TitleRule] {
+        self.rule_set.rules()
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,8 @@
+// {"e":"aggTrade","E":1711451862015,"s":"BTCUSDT","a":1855443,"p":"69801.50","q":"0.005","f":2206520,"l":2206520,"T":1711451861862,"m":false}
+use serde::Deserialize;
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct TradeStream {
+    pub p: String,
+}
@@ -4,4 +4,7 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct TreeResponse {
     pub title: String,
+    // Text of the announcement, when the source sends one
+    #[serde(default)]
+    pub body: String,
 }
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+//{"retCode":0,"retMsg":"OK","result":{"marginMode":"REGULAR_MARGIN","updatedTime":"1697078946000","unifiedMarginStatus":4,"dcpStatus":"OFF","timeWindow":10,"smpGroup":0,"isMasterTrader":false,"spotHedgingStatus":"OFF"},"retExtInfo":{},"time":1697078946000}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct AccountInfo {
+    pub result: Result,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Result {
+    pub marginMode: String,
+}
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+//{"retCode":110043,"retMsg":"leverage not modified","result":{},"retExtInfo":{},"time":1706557732801}
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct ApiResponse {
+    pub retCode: i64,
+    pub retMsg: String,
+}
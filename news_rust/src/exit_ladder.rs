@@ -0,0 +1,285 @@
+use crate::decimal_string;
+use crate::trailing_stop::{Retrace, TrailingStop};
+
+use fraction::Decimal;
+use serde::{Deserialize, Serialize};
+
+// Direction of the position the ladder closes
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Side {
+    #[default]
+    Long,
+    Short,
+}
+
+impl Side {
+    // `pct` away from the price in the position's favour
+    pub fn gain(self, price: Decimal, pct: Decimal) -> Decimal {
+        match self {
+            Side::Long => price * (Decimal::from(1) + pct),
+            Side::Short => price * (Decimal::from(1) - pct),
+        }
+    }
+
+    // `pct` away from the price against the position
+    pub fn loss(self, price: Decimal, pct: Decimal) -> Decimal {
+        match self {
+            Side::Long => price * (Decimal::from(1) - pct),
+            Side::Short => price * (Decimal::from(1) + pct),
+        }
+    }
+
+    // Whether the price is at or past a level on the profitable side
+    pub fn reached(self, price: Decimal, level: Decimal) -> bool {
+        match self {
+            Side::Long => price >= level,
+            Side::Short => price <= level,
+        }
+    }
+
+    // The better of two prices for the position, the higher one for a long
+    pub fn best(self, a: Decimal, b: Decimal) -> Decimal {
+        match self {
+            Side::Long => std::cmp::max(a, b),
+            Side::Short => std::cmp::min(a, b),
+        }
+    }
+
+    // Size of the position on this side from a signed position amount, shorts are negative
+    pub fn held(self, position_amt: Decimal) -> Decimal {
+        match self {
+            Side::Long => position_amt,
+            Side::Short => -position_amt,
+        }
+    }
+}
+
+// What the pct of a take profit is taken from
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TpBasis {
+    Original,
+    Remaining,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct TpInstance {
+    // Seconds after the entry
+    pub time: u64,
+    #[serde(with = "decimal_string")]
+    pub pct: Decimal,
+    pub basis: TpBasis,
+    // Sell early once the price has moved this fraction from the entry in the position's favour
+    #[serde(default, with = "decimal_string::option")]
+    pub price_target: Option<Decimal>,
+}
+
+// What the exits are sized and priced from
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub qty: Decimal,
+    pub price: Decimal,
+}
+
+// Trigger prices of the exchange side orders that close the position if the process can't
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Protection {
+    pub stop_price: Option<Decimal>,
+    pub target_price: Option<Decimal>,
+    pub side: Side,
+}
+
+// Why an exit step fired
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExitTrigger {
+    Time,
+    PriceTarget,
+    StopLoss,
+    TrailingStop,
+}
+
+impl TpInstance {
+    // Unrounded quantity the take profit sells
+    pub fn qty(&self, original_qty: Decimal, remaining_qty: Decimal) -> Decimal {
+        match self.basis {
+            TpBasis::Original => original_qty * self.pct,
+            TpBasis::Remaining => remaining_qty * self.pct,
+        }
+    }
+
+    pub fn target_price(&self, entry_price: Decimal, side: Side) -> Option<Decimal> {
+        self.price_target
+            .map(|target| side.gain(entry_price, target))
+    }
+}
+
+// The stop loss is checked first so a price that crosses both never takes profit on the way down
+pub fn price_trigger(
+    price: Decimal,
+    target_price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    side: Side,
+) -> Option<ExitTrigger> {
+    if stop_price.is_some_and(|stop_price| side.reached(stop_price, price)) {
+        Some(ExitTrigger::StopLoss)
+    } else if target_price.is_some_and(|target_price| side.reached(price, target_price)) {
+        Some(ExitTrigger::PriceTarget)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExitLadder {
+    steps: Vec<TpInstance>,
+    // Flattens the whole position once the price has moved this fraction against the entry
+    #[serde(default, with = "decimal_string::option")]
+    stop_loss: Option<Decimal>,
+    // Flattens the whole position once the price falls back from its peak, the last step is
+    // then the max holding time
+    #[serde(default)]
+    trailing_stop: Option<Retrace>,
+    #[serde(default)]
+    side: Side,
+}
+
+impl ExitLadder {
+    // The steps have to go forward in time and close exactly 100% of the original size
+    pub fn new(steps: Vec<TpInstance>) -> Result<ExitLadder, String> {
+        if steps.is_empty() {
+            return Err("the ladder has no steps".to_string());
+        }
+        let mut remaining = Decimal::from(1);
+        let mut previous_time = 0;
+        for (index, step) in steps.iter().enumerate() {
+            if step.time < previous_time {
+                return Err(format!(
+                    "step {} at {}s comes before the previous step at {}s",
+                    index, step.time, previous_time
+                ));
+            }
+            previous_time = step.time;
+            if step.pct <= Decimal::from(0) || step.pct > Decimal::from(1) {
+                return Err(format!("step {} pct {} is not in (0, 1]", index, step.pct));
+            }
+            if step
+                .price_target
+                .is_some_and(|target| target <= Decimal::from(0))
+            {
+                return Err(format!(
+                    "step {} price target has to be above the entry",
+                    index
+                ));
+            }
+            let closed = step.qty(Decimal::from(1), remaining);
+            if closed > remaining {
+                return Err(format!(
+                    "step {} sells {} of the original size but only {} is left",
+                    index, closed, remaining
+                ));
+            }
+            remaining -= closed;
+        }
+        if remaining != Decimal::from(0) {
+            return Err(format!(
+                "the steps leave {} of the original size open",
+                remaining
+            ));
+        }
+        Ok(ExitLadder {
+            steps,
+            stop_loss: None,
+            trailing_stop: None,
+            side: Side::Long,
+        })
+    }
+
+    pub fn with_stop_loss(self, stop_loss: Decimal) -> Result<ExitLadder, String> {
+        if stop_loss <= Decimal::from(0) || stop_loss >= Decimal::from(1) {
+            return Err(format!("stop loss {} is not in (0, 1)", stop_loss));
+        }
+        Ok(ExitLadder {
+            stop_loss: Some(stop_loss),
+            ..self
+        })
+    }
+
+    pub fn with_trailing_stop(self, retrace: Retrace) -> Result<ExitLadder, String> {
+        retrace.validate()?;
+        Ok(ExitLadder {
+            trailing_stop: Some(retrace),
+            ..self
+        })
+    }
+
+    // Targets and stops are mirrored below and above the entry for a short
+    pub fn with_side(self, side: Side) -> ExitLadder {
+        ExitLadder { side, ..self }
+    }
+
+    // Runs a ladder read from a file through the same checks as one built in code
+    pub fn validated(self) -> Result<ExitLadder, String> {
+        let exit_ladder = ExitLadder::new(self.steps)?.with_side(self.side);
+        let exit_ladder = match self.stop_loss {
+            Some(stop_loss) => exit_ladder.with_stop_loss(stop_loss)?,
+            None => exit_ladder,
+        };
+        match self.trailing_stop {
+            Some(retrace) => exit_ladder.with_trailing_stop(retrace),
+            None => Ok(exit_ladder),
+        }
+    }
+
+    pub fn trailing_stop(&self, entry_price: Decimal) -> Option<TrailingStop> {
+        self.trailing_stop
+            .map(|retrace| TrailingStop::new(retrace, entry_price, self.side))
+    }
+
+    pub fn stop_price(&self, entry_price: Decimal) -> Option<Decimal> {
+        self.stop_loss
+            .map(|stop_loss| self.side.loss(entry_price, stop_loss))
+    }
+
+    // Protection for the steps from `index` on. The stop follows the trailing stop once it is
+    // tighter than the hard stop, the target is the furthest target still ahead since the
+    // exchange order closes everything
+    pub fn protection(
+        &self,
+        entry_price: Decimal,
+        index: usize,
+        trailing_stop: Option<&TrailingStop>,
+    ) -> Protection {
+        let trailing_price = trailing_stop.and_then(|trailing_stop| trailing_stop.stop_price());
+        let stop_price = match (self.stop_price(entry_price), trailing_price) {
+            (Some(stop_price), Some(trailing_price)) => {
+                Some(self.side.best(stop_price, trailing_price))
+            }
+            (stop_price, trailing_price) => stop_price.or(trailing_price),
+        };
+        let target_price = self
+            .steps
+            .iter()
+            .skip(index)
+            .filter_map(|step| step.target_price(entry_price, self.side))
+            .reduce(|a, b| self.side.best(a, b));
+        Protection {
+            stop_price,
+            target_price,
+            side: self.side,
+        }
+    }
+
+    // Whether the exits need a live price or can just wait on the timer
+    pub fn watches_price(&self) -> bool {
+        self.stop_loss.is_some()
+            || self.trailing_stop.is_some()
+            || self.steps.iter().any(|step| step.price_target.is_some())
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn steps(&self) -> &[TpInstance] {
+        &self.steps
+    }
+}
@@ -0,0 +1,168 @@
+use fraction::Decimal;
+use log::info;
+
+// Trading rules of a single spot or futures instrument. A rule left at zero wasn't reported
+// by the exchange and isn't enforced.
+#[derive(Clone, Default, Debug)]
+pub struct InstrumentRules {
+    pub base_asset: String,
+    pub step_size: Decimal,
+    pub min_qty: Decimal,
+    pub max_qty: Decimal,
+    pub market_step_size: Decimal,
+    pub market_min_qty: Decimal,
+    pub market_max_qty: Decimal,
+    pub min_notional: Decimal,
+    pub max_notional: Decimal,
+    pub tick_size: Decimal,
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+    pub quote_step: Decimal,
+}
+
+fn first_set(market_rule: Decimal, rule: Decimal) -> Decimal {
+    if market_rule > Decimal::from(0) {
+        market_rule
+    } else {
+        rule
+    }
+}
+
+fn floor_to_step(value: Decimal, step: Decimal) -> Decimal {
+    if step > Decimal::from(0) {
+        (value / step).floor() * step
+    } else {
+        value
+    }
+}
+
+// Writes the value with exactly as many decimals as the step has, "0.00100000" has 3
+fn format_to_step(value: Decimal, step: Decimal) -> String {
+    let precision = if step > Decimal::from(0) {
+        step.calc_precision(None).get_precision() as usize
+    } else {
+        value.calc_precision(Some(18)).get_precision() as usize
+    };
+    format!("{:.1$}", floor_to_step(value, step), precision)
+}
+
+impl InstrumentRules {
+    // Market orders follow MARKET_LOT_SIZE when the exchange sets it and LOT_SIZE otherwise
+    pub fn market_step(&self) -> Decimal {
+        first_set(self.market_step_size, self.step_size)
+    }
+
+    fn market_min(&self) -> Decimal {
+        first_set(self.market_min_qty, self.min_qty)
+    }
+
+    fn market_max(&self) -> Decimal {
+        first_set(self.market_max_qty, self.max_qty)
+    }
+
+    // Quantity of a reduce only order closing the whole position. It's rounded up so dust under
+    // the step or the min qty gets closed too, reduce only orders never go past the position
+    pub fn close_qty(&self, position_qty: Decimal) -> Decimal {
+        let step = self.market_step();
+        let qty = position_qty.abs();
+        let qty = if step > Decimal::from(0) {
+            (qty / step).ceil() * step
+        } else {
+            qty
+        };
+        std::cmp::max(qty, self.market_min())
+    }
+
+    pub fn format_qty(&self, qty: Decimal) -> String {
+        format_to_step(qty, self.market_step())
+    }
+
+    pub fn format_quote_qty(&self, quote_qty: Decimal) -> String {
+        format_to_step(quote_qty, self.quote_step)
+    }
+
+    pub fn format_price(&self, price: Decimal) -> String {
+        format_to_step(price, self.tick_size)
+    }
+
+    pub fn validate_price(&self, price: Decimal) -> Result<Decimal, String> {
+        if price <= Decimal::from(0) {
+            return Err(format!("price {} is not positive", price));
+        }
+        if self.min_price > Decimal::from(0) && price < self.min_price {
+            return Err(format!(
+                "price {} is below the min price {}",
+                price, self.min_price
+            ));
+        }
+        if self.max_price > Decimal::from(0) && price > self.max_price {
+            return Err(format!(
+                "price {} is above the max price {}",
+                price, self.max_price
+            ));
+        }
+        Ok(floor_to_step(price, self.tick_size))
+    }
+
+    // Rounds the quantity down to the step and clamps it to the max market quantity. The
+    // quantity is rejected when it ends up under the min quantity or the min notional.
+    pub fn validate_market_qty(&self, qty: Decimal, price: Decimal) -> Result<Decimal, String> {
+        let step = self.market_step();
+        if step <= Decimal::from(0) {
+            return Err("no step size for the instrument".to_string());
+        }
+        let mut valid_qty = floor_to_step(qty, step);
+        let max_qty = self.market_max();
+        if max_qty > Decimal::from(0) && valid_qty > max_qty {
+            let max_qty = floor_to_step(max_qty, step);
+            info!(
+                "Clamping qty {} to the max market qty {}",
+                valid_qty, max_qty
+            );
+            valid_qty = max_qty;
+        }
+        if valid_qty <= Decimal::from(0) {
+            return Err(format!(
+                "qty {} rounds down to zero with step {}",
+                qty, step
+            ));
+        }
+        let min_qty = self.market_min();
+        if valid_qty < min_qty {
+            return Err(format!(
+                "qty {} is below the min qty {}",
+                valid_qty, min_qty
+            ));
+        }
+        if self.min_notional > Decimal::from(0) {
+            let price = self.validate_price(price)?;
+            if valid_qty * price < self.min_notional {
+                return Err(format!(
+                    "notional {} is below the min notional {}",
+                    valid_qty * price,
+                    self.min_notional
+                ));
+            }
+        }
+        Ok(valid_qty)
+    }
+
+    // Spot market buys are sized in the quote coin, so only the notional rules apply
+    pub fn validate_quote_qty(&self, quote_qty: Decimal) -> Result<Decimal, String> {
+        let mut valid_qty = floor_to_step(quote_qty, self.quote_step);
+        if self.max_notional > Decimal::from(0) && valid_qty > self.max_notional {
+            info!(
+                "Clamping quote qty {} to the max notional {}",
+                valid_qty, self.max_notional
+            );
+            valid_qty = self.max_notional;
+        }
+        if valid_qty <= Decimal::from(0) || valid_qty < self.min_notional {
+            return Err(format!(
+                "quote qty {} is below the min notional {}",
+                valid_qty, self.min_notional
+            ));
+        }
+        Ok(valid_qty)
+    }
+}
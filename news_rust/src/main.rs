@@ -1,21 +1,56 @@
+mod account_info;
+mod api_response;
+mod decimal_string;
+mod exit_ladder;
+mod exit_schedule;
+mod futures_setup;
+mod health;
+mod instrument_rules;
+mod open_orders;
 mod order_information;
 mod order_response;
 mod position_list;
+mod pre_trade;
 mod price_information;
+mod price_stream;
+mod private_stream;
+mod public_trade;
+mod reconcile;
+mod shutdown;
 mod symbol_information;
+mod symbol_resolution;
+mod ticker_aliases;
+mod title_rules;
+mod trailing_stop;
 mod tree_response;
+mod wallet_balance;
 
-use order_information::OrderInformation;
+use account_info::AccountInfo;
+use api_response::ApiResponse;
+use exit_ladder::{Entry, ExitLadder, ExitTrigger, Protection, Side, TpBasis, TpInstance};
+use exit_schedule::{LegClaim, PendingExit, ScheduledBuy, EXIT_SCHEDULE_PATH, SCHEDULED_BUYS_PATH};
+use futures_setup::{FuturesSetup, MarginMode};
+use health::Busy;
+use instrument_rules::InstrumentRules;
+use open_orders::OpenOrders;
+use order_information::{OrderInformation, OrderInformationList};
 use order_response::OrderResponse;
 use position_list::PositionList;
+use pre_trade::PriceQuote;
 use price_information::PriceInformation;
-use symbol_information::SymbolInformation;
+use private_stream::PrivateStream;
+use reconcile::{Holding, OrphanPolicy};
+use shutdown::ShutdownPolicy;
+use symbol_information::{Symbol, SymbolInformation};
+use symbol_resolution::{ResolvedSymbol, VenueNaming, BYBIT_LINEAR, BYBIT_SPOT};
+use ticker_aliases::{TickerAliases, TICKER_ALIASES_PATH};
+use title_rules::{SpotEntry, TitleRule, TitleRules, TITLE_RULES_PATH};
 use tree_response::TreeResponse;
+use wallet_balance::WalletBalance;
 
-use fancy_regex::Regex;
 use fraction::Decimal;
 use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use hmac::Mac;
 use log::{error, info};
 use reqwest::{
@@ -24,46 +59,81 @@ use reqwest::{
 };
 use std::future::Future;
 use std::{collections::HashMap, env, error, pin::Pin};
-use tokio::time::{sleep, Duration};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, tungstenite::Result};
 
-#[derive(Eq, PartialEq, Hash)]
-enum TpCases {
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize)]
+pub enum TpCases {
     BinanceListing,
-    UpbitListing,
+    UpbitKrwListing,
+    UpbitBtcListing,
+    UpbitUsdtListing,
     BinanceFuturesListing,
     BithumbListing,
-    NoListing,
+    CoinbaseListing,
+    CoinbaseRoadmap,
+    OkxListing,
+    BybitListing,
+    BinanceLaunchpool,
+    BinanceHodlerAirdrop,
+    BinanceMegadrop,
+    BinanceDelisting,
+    UpbitInvestmentWarning,
+    UpbitDelisting,
 }
 
-#[derive(Copy, Clone)]
-struct TpInstance {
-    time: u64,
-    pct: f32,
+// What the venues of a title rule can name
+const TITLE_RULE_VENUES: &[&str] = &["linear", "spot"];
+
+// Order sides that open and close a position
+fn order_sides(side: Side) -> (&'static str, &'static str) {
+    match side {
+        Side::Long => ("Buy", "Sell"),
+        Side::Short => ("Sell", "Buy"),
+    }
 }
-const EMPTY_TP_CASE: [TpInstance; 2] = [TpInstance { time: 0, pct: 0.0 }; 2];
 
-async fn market_buy_futures_position(
+async fn market_open_futures_position(
     client: Client,
     symbol: String,
-    qty: f32,
-    qty_step: f32,
-    tp_instance_arr: &[TpInstance; 2],
+    qty: Decimal,
+    futures_rules: InstrumentRules,
+    futures_setup: FuturesSetup,
+    exit_ladder: ExitLadder,
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
-    let price: f32 = get_price(client.clone(), &symbol).await?;
-    let leverage: f32 = get_leverage(client.clone(), &symbol, recv_window).await?;
-
-    let qty = Decimal::from(qty);
-    let qty_step_dec = Decimal::from(qty_step);
-    let leverage = Decimal::from(leverage);
-    let price = Decimal::from(price);
-    let qty = (qty * leverage / price / qty_step_dec).floor() * qty_step_dec;
+    let busy = Busy::start();
+    let (quote, setup) = futures::join!(
+        get_price_quote(client.clone(), &symbol, "linear"),
+        apply_futures_setup(client.clone(), &symbol, futures_setup, recv_window)
+    );
+    let quote = quote?;
+    let (leverage, margin_mode) = setup?;
+    let checks = pre_trade::check_price(&quote, chrono::Utc::now().timestamp_millis())
+        .and_then(|_| pre_trade::check_leverage(leverage))
+        .and_then(|_| futures_setup.verify(leverage, margin_mode));
+    if let Err(e) = checks {
+        error!("Skipping futures leg {}: {}", symbol, e);
+        return Ok(());
+    }
+    let price = quote.price;
+
+    let qty = match futures_rules.validate_market_qty(qty * leverage / price, price) {
+        Ok(qty) => qty,
+        Err(e) => {
+            error!("Not opening futures {}: {}", symbol, e);
+            return Ok(());
+        }
+    };
     let url = "https://api-testnet.bybit.com/v5/order/create";
+    let (entry_side, _) = order_sides(exit_ladder.side());
 
     let payload = format!(
-        r#"{{"category":"linear","symbol":"{}","side":"Buy","orderType":"Market","qty":"{}"}}"#,
-        symbol, qty
+        r#"{{"category":"linear","symbol":"{}","side":"{}","orderType":"Market","qty":"{}"}}"#,
+        symbol,
+        entry_side,
+        futures_rules.format_qty(qty)
     );
 
     if let Ok(res) = client
@@ -75,46 +145,134 @@ async fn market_buy_futures_position(
     {
         let body = res.text().await?;
 
-        info!("Buy Futures Status {} = {}", &symbol, &body);
+        info!("{} Futures Status {} = {}", entry_side, &symbol, &body);
 
-        let qty: f32 = qty
-            .to_string()
-            .parse()
-            .expect("Failed to parse base coin qty");
-
-        if tp_instance_arr[0].time != 0 {
-            market_sell_position(
-                client,
-                &symbol,
-                qty,
-                qty_step,
-                "linear",
-                tp_instance_arr,
-                recv_window,
-            )
-            .await?;
-        } else {
-            error!("Failed to sell {} {}", symbol, body);
-        }
+        let pending_exit = PendingExit::new(
+            &symbol,
+            "linear",
+            Entry { qty, price },
+            &exit_ladder,
+            chrono::Utc::now().timestamp_millis(),
+        );
+        drop(busy);
+        market_sell_position(client, pending_exit, &futures_rules, recv_window).await?;
     } else {
         error!("Error in sending the futures order {}", symbol);
     }
     Ok(())
 }
 
+// The position size is notional over the instrument's own price, so a multiplied contract needs
+// no scaling, its multiplier only tells how many coins are held
+fn log_resolved(ticker: &str, resolved: &ResolvedSymbol) {
+    info!(
+        "{} trades as {}, {} coins per unit",
+        ticker, resolved.symbol, resolved.multiplier
+    );
+}
+
+// How long a scheduled buy keeps looking for the new pair after the listing time
+const LISTING_WAIT: Duration = Duration::from_secs(60);
+// How often the instrument lists the feed resolves tickers against are fetched again
+const INSTRUMENT_REFRESH: Duration = Duration::from_secs(15);
+// Wait before connecting to the feed again, doubled after every failed attempt up to the max
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+// Tries at a take profit before its quantity is left for the next step
+const SELL_ATTEMPTS: u32 = 3;
+
+// Buys spot once the pair opens at the announced listing time. The buy stays in the scheduled
+// buys until it is placed or given up, so a restart before then still makes it
+async fn scheduled_buy_spot_position(
+    client: Client,
+    scheduled_buy: ScheduledBuy,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let ticker = scheduled_buy.ticker.clone();
+    let done = || {
+        if let Err(e) = exit_schedule::remove_scheduled_buy(SCHEDULED_BUYS_PATH, &ticker) {
+            error!("Failed to remove the scheduled buy of {}: {}", ticker, e);
+        }
+    };
+    let listing_time =
+        chrono::DateTime::from_timestamp_millis(scheduled_buy.listing_time_ms).unwrap_or_default();
+    let until_listing = match (listing_time - chrono::Utc::now()).to_std() {
+        Ok(until_listing) => until_listing,
+        Err(_) => {
+            error!(
+                "Not buying spot {}: listing time {} has passed",
+                ticker, listing_time
+            );
+            done();
+            return Ok(());
+        }
+    };
+    info!("Buying spot {} at {}", ticker, listing_time);
+    tokio::time::sleep(until_listing).await;
+    let deadline = Instant::now() + LISTING_WAIT;
+    while Instant::now() < deadline {
+        // A failed lookup is tried again like a pair that isn't open yet
+        let (spot_symbol, spot_rules) =
+            match resolve_instrument(client.clone(), &ticker, &BYBIT_SPOT, "spot").await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    error!("Failed to look up spot {}: {}", ticker, e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+        if let Ok(spot_rules) = pre_trade::check_instrument(spot_rules.as_ref()) {
+            done();
+            let claim = match LegClaim::claim(&spot_symbol.symbol, "spot") {
+                Ok(claim) => claim,
+                Err(e) => {
+                    error!("Not buying spot {}: {}", ticker, e);
+                    return Ok(());
+                }
+            };
+            return claim
+                .hold(market_buy_spot_position(
+                    client,
+                    spot_symbol.symbol,
+                    scheduled_buy.unit_coin_qty,
+                    spot_rules,
+                    scheduled_buy.exit_ladder,
+                    recv_window,
+                ))
+                .await;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    error!(
+        "Not buying spot {}: the pair didn't open after {}",
+        ticker, listing_time
+    );
+    done();
+    Ok(())
+}
+
 async fn market_buy_spot_position(
     client: Client,
     symbol: String,
-    unit_qty: f32,
-    qty_step: f32,
-    tp_instance_arr: &[TpInstance; 2],
+    unit_qty: Decimal,
+    spot_rules: InstrumentRules,
+    exit_ladder: ExitLadder,
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
+    let busy = Busy::start();
+    let unit_qty = match spot_rules.validate_quote_qty(unit_qty) {
+        Ok(unit_qty) => unit_qty,
+        Err(e) => {
+            error!("Not buying spot {}: {}", symbol, e);
+            return Ok(());
+        }
+    };
     let url = "https://api-testnet.bybit.com/v5/order/create";
 
     let payload = format!(
         r#"{{"category":"spot","symbol":"{}", "side":"Buy", "orderType":"Market","qty":"{}"}}"#,
-        symbol, unit_qty
+        symbol,
+        spot_rules.format_quote_qty(unit_qty)
     );
 
     if let Ok(res) = client
@@ -131,24 +289,44 @@ async fn market_buy_spot_position(
         let order_response: Result<OrderResponse, _> = serde_json::from_str(&body);
 
         if let Ok(order) = order_response {
-            if tp_instance_arr[0].time != 0 {
-                let qty = get_order_qty(client.clone(), &order.result.orderId, recv_window).await?;
-                let price = get_price(client.clone(), &symbol).await?;
-
-                let tp_qty = ((qty / price) / qty_step).floor() * qty_step;
-                market_sell_position(
-                    client,
-                    &symbol,
-                    tp_qty,
-                    qty_step,
-                    "spot",
-                    tp_instance_arr,
-                    recv_window,
-                )
-                .await?;
-            } else {
-                error!("Failed to sell {} {}", symbol, body);
+            let fill = match get_spot_fill(client.clone(), &order.result.orderId, recv_window).await
+            {
+                Ok(fill) => fill,
+                Err(e) => {
+                    error!(
+                        "Bought spot {} but can't read the order, no exit is running for it: {}",
+                        symbol, e
+                    );
+                    return Ok(());
+                }
+            };
+            let base_coin_qty = spot_filled_base_qty(&fill);
+            info!(
+                "Spot {} filled {} {} for {} USDT",
+                symbol, base_coin_qty, spot_rules.base_asset, fill.cumExecValue
+            );
+            if base_coin_qty <= Decimal::from(0) {
+                error!(
+                    "Spot {} order ended {} with nothing filled",
+                    symbol, fill.orderStatus
+                );
+                return Ok(());
             }
+            let quote_qty: Decimal = fill.cumExecValue.parse().unwrap_or_default();
+            let filled_qty: Decimal = fill.cumExecQty.parse().unwrap_or_default();
+            let entry_price = quote_qty / filled_qty;
+            let pending_exit = PendingExit::new(
+                &symbol,
+                "spot",
+                Entry {
+                    qty: base_coin_qty,
+                    price: entry_price,
+                },
+                &exit_ladder,
+                chrono::Utc::now().timestamp_millis(),
+            );
+            drop(busy);
+            market_sell_position(client, pending_exit, &spot_rules, recv_window).await?;
         } else {
             error!("Failed to buy {} {}", symbol, body);
         }
@@ -159,49 +337,720 @@ async fn market_buy_spot_position(
     Ok(())
 }
 
-async fn market_sell_position(
+async fn get_spot_balances(
+    client: Client,
+    recv_window: &str,
+) -> Result<Vec<wallet_balance::Coin>, Box<dyn error::Error>> {
+    let params = "accountType=UNIFIED";
+    let url = format!(
+        "https://api-testnet.bybit.com/v5/account/wallet-balance?{}",
+        params
+    );
+    let res = client
+        .get(&url)
+        .headers(construct_headers(params, recv_window))
+        .send()
+        .await?;
+    let body = res.text().await?;
+    let wallet_balance: WalletBalance = serde_json::from_str(&body)?;
+
+    Ok(wallet_balance
+        .result
+        .list
+        .into_iter()
+        .flat_map(|account| account.coin)
+        .collect())
+}
+
+async fn get_spot_balance(
+    client: Client,
+    coin: &str,
+    recv_window: &str,
+) -> Result<Decimal, Box<dyn error::Error>> {
+    Ok(get_spot_balances(client, recv_window)
+        .await?
+        .iter()
+        .find(|balance| balance.coin == coin)
+        .map(|balance| balance.walletBalance.parse().unwrap_or_default())
+        .unwrap_or_default())
+}
+
+async fn get_open_positions(
+    client: Client,
+    recv_window: &str,
+) -> Result<Vec<position_list::LeverageList>, Box<dyn error::Error>> {
+    let params = "category=linear&settleCoin=USDT";
+    let url = format!("https://api-testnet.bybit.com/v5/position/list?{}", params);
+    let res = client
+        .get(&url)
+        .headers(construct_headers(params, recv_window))
+        .send()
+        .await?;
+    let body = res.text().await?;
+    let position_list: PositionList = serde_json::from_str(&body)?;
+
+    Ok(position_list
+        .result
+        .list
+        .into_iter()
+        .filter(|position| position.size.parse::<Decimal>().unwrap_or_default() != Decimal::from(0))
+        .collect())
+}
+
+async fn get_open_orders(
+    client: Client,
+    category: &str,
+    recv_window: &str,
+) -> Result<Vec<open_orders::OpenOrder>, Box<dyn error::Error>> {
+    let params = match category {
+        "linear" => String::from("category=linear&settleCoin=USDT"),
+        _ => format!("category={}", category),
+    };
+    let url = format!("https://api-testnet.bybit.com/v5/order/realtime?{}", params);
+    let res = client
+        .get(&url)
+        .headers(construct_headers(&params, recv_window))
+        .send()
+        .await?;
+    let body = res.text().await?;
+    let open_orders: OpenOrders = serde_json::from_str(&body)?;
+
+    Ok(open_orders.result.list)
+}
+
+async fn cancel_open_orders(
     client: Client,
     symbol: &str,
-    qty: f32,
-    qty_step: f32,
     category: &str,
-    tp_instance_arr: &[TpInstance; 2],
     recv_window: &str,
 ) -> Result<(), Box<dyn error::Error>> {
+    let payload = format!(r#"{{"category":"{}","symbol":"{}"}}"#, category, symbol);
+    let response = post_position_request(
+        client,
+        "https://api-testnet.bybit.com/v5/order/cancel-all",
+        payload,
+        recv_window,
+    )
+    .await?;
+    info!(
+        "Cancel open orders for {} {}: {} {}",
+        category, symbol, response.retCode, response.retMsg
+    );
+    Ok(())
+}
+
+// Linear positions and the spot coins that trade against USDT. Shorts get a negative qty
+async fn get_holdings(
+    client: Client,
+    recv_window: &str,
+) -> Result<Vec<Holding>, Box<dyn error::Error>> {
+    let mut holdings: Vec<Holding> = get_open_positions(client.clone(), recv_window)
+        .await?
+        .into_iter()
+        .map(|position| {
+            let size: Decimal = position.size.parse().unwrap_or_default();
+            Holding {
+                symbol: position.symbol,
+                category: String::from("linear"),
+                qty: if position.side == "Sell" { -size } else { size },
+                entry_price: position.avgPrice.parse().unwrap_or_default(),
+            }
+        })
+        .collect();
+    for balance in get_spot_balances(client, recv_window).await? {
+        if balance.coin == "USDT" {
+            continue;
+        }
+        holdings.push(Holding {
+            symbol: format!("{}USDT", balance.coin),
+            category: String::from("spot"),
+            qty: balance.walletBalance.parse().unwrap_or_default(),
+            entry_price: Decimal::from(0),
+        });
+    }
+    Ok(holdings)
+}
+
+// Sells a spot balance at market, rounded down to what the symbol accepts
+async fn flatten_spot_balance(
+    client: Client,
+    symbol: &str,
+    qty: Decimal,
+    spot_rules: &InstrumentRules,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let price = get_price(client.clone(), symbol, "spot").await?;
+    let qty = match spot_rules.validate_market_qty(qty, price) {
+        Ok(qty) => qty,
+        Err(e) => {
+            error!("Not flattening spot {}: {}", symbol, e);
+            return Ok(());
+        }
+    };
+    let payload = format!(
+        r#"{{"category":"spot","symbol":"{}","side":"Sell","orderType":"Market","qty":"{}"}}"#,
+        symbol,
+        spot_rules.format_qty(qty)
+    );
+    let res = client
+        .post("https://api-testnet.bybit.com/v5/order/create")
+        .headers(construct_headers(&payload, recv_window))
+        .body(payload)
+        .send()
+        .await?;
+    let body = res.text().await?;
+
+    info!("Flatten spot {} Status = {}", symbol, &body);
+    // A rejection comes back with a retCode and no orderId
+    match serde_json::from_str::<OrderResponse>(&body) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Flatten of spot {} not taken", symbol).into()),
+    }
+}
+
+// Compares what the exchange holds with the pending exits and reports the rest. Depending on the
+// policy the orphans are left alone, put on the adopt ladder or closed at market. A short is
+// adopted on the mirrored ladder, which buys it back and takes profit below the entry
+async fn reconcile_orphans(
+    client: Client,
+    orphan_policy: OrphanPolicy,
+    adopt_ladder: &ExitLadder,
+    recv_window: &str,
+) -> Result<Vec<(PendingExit, InstrumentRules)>, Box<dyn error::Error>> {
+    let pending_exits = exit_schedule::load_exits(EXIT_SCHEDULE_PATH);
+    let holdings = get_holdings(client.clone(), recv_window).await?;
+    let mut open_orders = Vec::new();
+    for category in ["linear", "spot"] {
+        for open_order in get_open_orders(client.clone(), category, recv_window).await? {
+            open_orders.push((open_order.symbol, category.to_string()));
+        }
+    }
+    for (symbol, category) in reconcile::find_orphan_orders(open_orders, &pending_exits) {
+        info!("Orphaned {} orders on {}", category, symbol);
+    }
+
+    let mut adopted = Vec::new();
+    for holding in reconcile::find_orphans(holdings, &pending_exits) {
+        info!(
+            "Orphaned {} position {} of {} at {}",
+            holding.category, holding.symbol, holding.qty, holding.entry_price
+        );
+        if orphan_policy == OrphanPolicy::Report {
+            continue;
+        }
+        let symbol_rules =
+            match get_instrument_rules(client.clone(), &holding.symbol, &holding.category).await? {
+                Some(symbol_rules) => symbol_rules,
+                None => {
+                    error!(
+                        "No rules for orphaned {} {}",
+                        holding.category, holding.symbol
+                    );
+                    continue;
+                }
+            };
+        // Orders on an orphan would fight the new exit
+        cancel_open_orders(
+            client.clone(),
+            &holding.symbol,
+            &holding.category,
+            recv_window,
+        )
+        .await?;
+        let adopt = orphan_policy == OrphanPolicy::Adopt && holding.qty != Decimal::from(0);
+        if adopt {
+            let entry_price = if holding.entry_price > Decimal::from(0) {
+                holding.entry_price
+            } else {
+                get_price(client.clone(), &holding.symbol, &holding.category).await?
+            };
+            info!(
+                "Adopting {} {} from {}",
+                holding.category, holding.symbol, entry_price
+            );
+            // Shorts get the same ladder mirrored
+            let side = if holding.qty < Decimal::from(0) {
+                Side::Short
+            } else {
+                Side::Long
+            };
+            let pending_exit = PendingExit::new(
+                &holding.symbol,
+                &holding.category,
+                Entry {
+                    qty: side.held(holding.qty),
+                    price: entry_price,
+                },
+                &adopt_ladder.clone().with_side(side),
+                chrono::Utc::now().timestamp_millis(),
+            );
+            adopted.push((pending_exit, symbol_rules));
+        } else {
+            let closed = if holding.category == "linear" {
+                sweep_residual_position(client.clone(), &holding.symbol, &symbol_rules, recv_window)
+                    .await
+            } else {
+                flatten_spot_balance(
+                    client.clone(),
+                    &holding.symbol,
+                    holding.qty,
+                    &symbol_rules,
+                    recv_window,
+                )
+                .await
+            };
+            if let Err(e) = closed {
+                error!(
+                    "Failed to close orphan {} {}: {}",
+                    holding.category, holding.symbol, e
+                );
+            }
+        }
+    }
+    Ok(adopted)
+}
+
+// Kill switch: cancels every open order and closes every linear position, plus the spot balance
+// of every exit the bot has pending. Spot balances the bot didn't open are left alone. Failures
+// are logged and skipped so one bad symbol doesn't keep the rest open
+async fn flatten_all(client: Client, recv_window: &str) -> Result<(), Box<dyn error::Error>> {
+    error!("Flattening everything");
+    for payload in [
+        r#"{"category":"linear","settleCoin":"USDT"}"#,
+        r#"{"category":"spot"}"#,
+    ] {
+        match post_position_request(
+            client.clone(),
+            "https://api-testnet.bybit.com/v5/order/cancel-all",
+            payload.to_string(),
+            recv_window,
+        )
+        .await
+        {
+            Ok(response) => info!(
+                "Cancel all {}: {} {}",
+                payload, response.retCode, response.retMsg
+            ),
+            Err(e) => error!("Failed to cancel all {}: {}", payload, e),
+        }
+    }
+
+    for position in get_open_positions(client.clone(), recv_window).await? {
+        let futures_rules = get_instrument_rules(client.clone(), &position.symbol, "linear")
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        if let Err(e) = sweep_residual_position(
+            client.clone(),
+            &position.symbol,
+            &futures_rules,
+            recv_window,
+        )
+        .await
+        {
+            error!("Failed to close linear {}: {}", position.symbol, e);
+        }
+    }
+
+    for pending_exit in exit_schedule::load_exits(EXIT_SCHEDULE_PATH) {
+        if pending_exit.category != "spot" {
+            continue;
+        }
+        let spot_rules =
+            match get_instrument_rules(client.clone(), &pending_exit.symbol, "spot").await {
+                Ok(Some(spot_rules)) => spot_rules,
+                _ => {
+                    error!("No rules to flatten spot {}", pending_exit.symbol);
+                    continue;
+                }
+            };
+        let flattened =
+            match get_spot_balance(client.clone(), &spot_rules.base_asset, recv_window).await {
+                Ok(balance) => {
+                    flatten_spot_balance(
+                        client.clone(),
+                        &pending_exit.symbol,
+                        balance,
+                        &spot_rules,
+                        recv_window,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+        if let Err(e) = flattened {
+            error!("Failed to flatten spot {}: {}", pending_exit.symbol, e);
+        }
+    }
+    exit_schedule::clear_exits(EXIT_SCHEDULE_PATH)?;
+    exit_schedule::clear_scheduled_buys(SCHEDULED_BUYS_PATH)?;
+    Ok(())
+}
+
+// Picks up the exits a previous run left unfinished. Each is checked against what is still held,
+// exits whose position is gone are dropped and the rest are cut down to the live size. Steps that
+// came due while the bot was down run as soon as the exit is resumed
+async fn resume_exits(client: Client, recv_window: &str) -> Vec<(PendingExit, InstrumentRules)> {
+    let mut resumed = Vec::new();
+    for mut pending_exit in exit_schedule::load_exits(EXIT_SCHEDULE_PATH) {
+        let symbol = pending_exit.symbol.clone();
+        let category = pending_exit.category.clone();
+        let symbol_rules = match get_instrument_rules(client.clone(), &symbol, &category).await {
+            Ok(Some(symbol_rules)) => symbol_rules,
+            _ => {
+                error!(
+                    "No rules for pending {} exit {}, leaving it",
+                    category, symbol
+                );
+                continue;
+            }
+        };
+        let held_qty = match category.as_str() {
+            "linear" => get_position(client.clone(), &symbol, recv_window)
+                .await
+                .map(|position| {
+                    position
+                        .filter(|position| {
+                            position.side == order_sides(pending_exit.exit_ladder.side()).0
+                        })
+                        .and_then(|position| position.size.parse().ok())
+                        .unwrap_or_default()
+                }),
+            _ => get_spot_balance(client.clone(), &symbol_rules.base_asset, recv_window).await,
+        };
+        let held_qty: Decimal = match held_qty {
+            Ok(held_qty) => held_qty,
+            Err(e) => {
+                error!("Failed to reconcile {} exit {}: {}", category, symbol, e);
+                continue;
+            }
+        };
+        if held_qty <= Decimal::from(0) {
+            info!("{} {} was closed while the bot was down", category, symbol);
+            if let Err(e) = exit_schedule::remove_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+                error!(
+                    "Failed to remove the exit of {} {}: {}",
+                    category, symbol, e
+                );
+            }
+            continue;
+        }
+        pending_exit.remaining_qty = std::cmp::min(pending_exit.remaining_qty, held_qty);
+        info!(
+            "Resuming {} exit {} at step {} with {} left",
+            category, symbol, pending_exit.next_step, pending_exit.remaining_qty
+        );
+        resumed.push((pending_exit, symbol_rules));
+    }
+    resumed
+}
+
+async fn market_sell_position(
+    client: Client,
+    mut pending_exit: PendingExit,
+    symbol_rules: &InstrumentRules,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let symbol = pending_exit.symbol.clone();
+    let symbol = symbol.as_str();
+    let category = pending_exit.category.clone();
+    let category = category.as_str();
+    let exit_ladder = pending_exit.exit_ladder.clone();
+    let side = exit_ladder.side();
     let url = "https://api-testnet.bybit.com/v5/order/create";
 
-    for tp in tp_instance_arr {
-        let seconds: u64 = tp.time;
-        sleep(Duration::from_secs(seconds)).await;
-        let tp_pct = Decimal::from(tp.pct);
-        let qty_step_dec = Decimal::from(qty_step);
-        let qty_dec = Decimal::from(qty);
-        let tp_qty = ((qty_dec / qty_step_dec) * tp_pct).floor() * qty_step_dec;
+    let busy = Busy::start();
+    let qty_step = symbol_rules.market_step();
+    if qty_step == Decimal::from(0) {
+        error!("No step size for {} {}, not selling", category, symbol);
+        return Ok(());
+    }
+
+    // Take profit times are absolute due times, so slow sells don't push back later steps and
+    // a resumed exit keeps its original timing
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let since_entry = Duration::from_millis((now_ms - pending_exit.entry_time_ms).max(0) as u64);
+    let entry_time = Instant::now()
+        .checked_sub(since_entry)
+        .unwrap_or_else(Instant::now);
+    let entry_price = pending_exit.entry_price;
+    let original_qty = (pending_exit.original_qty / qty_step).floor() * qty_step;
+    let mut remaining_qty = (pending_exit.remaining_qty / qty_step).floor() * qty_step;
+    // Without an entry price there is nothing to put the targets and the stop against
+    let watch_price = exit_ladder.watches_price() && entry_price > Decimal::from(0);
+    let mut price_stream = if watch_price {
+        price_stream::connect_price_stream(symbol, category).await
+    } else {
+        None
+    };
+    let stop_price = exit_ladder.stop_price(entry_price).filter(|_| watch_price);
+    let mut trailing_stop = exit_ladder
+        .trailing_stop(pending_exit.peak_price)
+        .filter(|_| watch_price);
+    let protect = watch_price && category == "linear";
+    let tp_steps = exit_ladder.steps();
+    let mut unsold = false;
+    for (index, tp) in tp_steps.iter().enumerate().skip(pending_exit.next_step) {
+        pending_exit.next_step = index;
+        pending_exit.remaining_qty = remaining_qty;
+        if let Some(trailing_stop) = trailing_stop.as_ref() {
+            pending_exit.peak_price = trailing_stop.peak();
+        }
+        if let Err(e) = exit_schedule::save_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+            error!("Failed to save the exit of {} {}: {}", category, symbol, e);
+        }
+        // The TP/SL is set again for every step so it follows the trailing stop and the targets
+        // still ahead
+        if protect && remaining_qty > Decimal::from(0) {
+            let protection = exit_ladder.protection(entry_price, index, trailing_stop.as_ref());
+            if let Err(e) = set_trading_stop(
+                client.clone(),
+                symbol,
+                symbol_rules,
+                protection,
+                recv_window,
+            )
+            .await
+            {
+                error!("Failed to protect {} {}: {}", category, symbol, e);
+            }
+        }
+        let due_in = pending_exit.due_ms.get(index).copied().unwrap_or(now_ms)
+            - chrono::Utc::now().timestamp_millis();
+        busy.idle();
+        let trigger = price_stream::wait_for_exit(
+            &mut price_stream,
+            entry_time,
+            Instant::now() + Duration::from_millis(due_in.max(0) as u64),
+            tp.target_price(entry_price, side).filter(|_| watch_price),
+            stop_price,
+            &mut trailing_stop,
+            side,
+        )
+        .await;
+        busy.resume();
+        info!(
+            "Exit step {} for {} {}: {:?}",
+            index, category, symbol, trigger
+        );
+        let flatten = matches!(trigger, ExitTrigger::StopLoss | ExitTrigger::TrailingStop);
+        // The last take profit and the stops sell whatever is left so rounding doesn't leave
+        // a residual
+        let tp_qty = if index == tp_steps.len() - 1 || flatten {
+            remaining_qty
+        } else {
+            std::cmp::min(
+                (tp.qty(original_qty, remaining_qty) / qty_step).floor() * qty_step,
+                remaining_qty,
+            )
+        };
+        if tp_qty <= Decimal::from(0) {
+            continue;
+        }
+        let price = if symbol_rules.min_notional > Decimal::from(0) {
+            get_price(client.clone(), symbol, category).await?
+        } else {
+            Decimal::from(0)
+        };
+        let tp_qty = match symbol_rules.validate_market_qty(tp_qty, price) {
+            Ok(tp_qty) => tp_qty,
+            Err(e) => {
+                error!("Not selling {} {}: {}", category, symbol, e);
+                continue;
+            }
+        };
+        // Futures exits can only shrink the position, so rounding or a partial entry can't
+        // flip it to the other side
+        let reduce_only = if category == "linear" {
+            r#","reduceOnly":true"#
+        } else {
+            ""
+        };
+        let (_, exit_side) = order_sides(side);
         let payload = format!(
-            r#"{{"category":"{}","symbol":"{}","side":"Sell","orderType":"Market","qty":"{}"}}"#,
-            category, symbol, tp_qty
+            r#"{{"category":"{}","symbol":"{}","side":"{}","orderType":"Market","qty":"{}"{}}}"#,
+            category,
+            symbol,
+            exit_side,
+            symbol_rules.format_qty(tp_qty),
+            reduce_only
         );
 
         info!("payload = {}", payload);
 
-        let res = client
-            .post(url)
-            .headers(construct_headers(&payload, recv_window))
-            .body(payload)
-            .send()
-            .await?;
-        let body = res.text().await?;
+        // Only an order the exchange took counts as sold, a rejection comes back with a retCode
+        // and no orderId and leaves the quantity held
+        let mut sold = false;
+        for attempt in 1..=SELL_ATTEMPTS {
+            match client
+                .post(url)
+                .headers(construct_headers(&payload, recv_window))
+                .body(payload.clone())
+                .send()
+                .await
+            {
+                Ok(res) => {
+                    let body = res.text().await?;
+                    info!("Sell Status = {}, Category = {}", &body, category);
+                    match serde_json::from_str::<OrderResponse>(&body) {
+                        Ok(order_response) => {
+                            info!(
+                                "Sold {} {} with order {}",
+                                category, symbol, order_response.result.orderId
+                            );
+                            sold = true;
+                            break;
+                        }
+                        Err(_) => error!(
+                            "Sell of {} {} not taken, attempt {}/{}",
+                            category, symbol, attempt, SELL_ATTEMPTS
+                        ),
+                    }
+                }
+                Err(e) => error!(
+                    "Error in sending the sell order {}, attempt {}/{}: {}",
+                    symbol, attempt, SELL_ATTEMPTS, e
+                ),
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        if sold {
+            remaining_qty -= tp_qty;
+            pending_exit.next_step = index + 1;
+            pending_exit.remaining_qty = remaining_qty;
+            if let Err(e) = exit_schedule::save_exit(EXIT_SCHEDULE_PATH, &pending_exit) {
+                error!("Failed to save the exit of {} {}: {}", category, symbol, e);
+            }
+        } else {
+            error!(
+                "Gave up selling {} of {} {}, {} is still held",
+                tp_qty, category, symbol, remaining_qty
+            );
+        }
+        unsold = !sold;
+        if flatten {
+            break;
+        }
+    }
+
+    // The exit stays in the schedule until the position is known to be closed, so the next
+    // start resumes or reconciles what the exchange didn't take
+    if unsold {
+        return Err(format!(
+            "{} of {} {} is still held, its exit stays in the schedule",
+            remaining_qty, category, symbol
+        )
+        .into());
+    }
+    if category == "linear" {
+        sweep_residual_position(client, symbol, symbol_rules, recv_window).await?;
+    } else if remaining_qty > Decimal::from(0) {
+        // A step skipped as too small leaves its qty behind, sold now if it's enough on its own
+        let held_qty =
+            get_spot_balance(client.clone(), &symbol_rules.base_asset, recv_window).await?;
+        flatten_spot_balance(
+            client,
+            symbol,
+            std::cmp::min(remaining_qty, held_qty),
+            symbol_rules,
+            recv_window,
+        )
+        .await?;
+    }
+    exit_schedule::remove_exit(EXIT_SCHEDULE_PATH, &pending_exit)?;
+    Ok(())
+}
+
+// Closes whatever is left on the futures position after the last take profit, whether it's a
+// long, a short or dust under the min qty
+async fn sweep_residual_position(
+    client: Client,
+    symbol: &str,
+    futures_rules: &InstrumentRules,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let position = get_position(client.clone(), symbol, recv_window)
+        .await?
+        .unwrap_or_default();
+    let size: Decimal = position.size.parse().unwrap_or_default();
+    let side = match position.side.as_str() {
+        "Buy" => "Sell",
+        "Sell" => "Buy",
+        _ => "",
+    };
+    if size == Decimal::from(0) || side.is_empty() {
+        info!("No residual position for {}", symbol);
+        return Ok(());
+    }
+    let payload = format!(
+        r#"{{"category":"linear","symbol":"{}","side":"{}","orderType":"Market","qty":"{}","reduceOnly":true}}"#,
+        symbol,
+        side,
+        futures_rules.format_qty(futures_rules.close_qty(size))
+    );
+    info!(
+        "Sweeping residual {} position {}: {}",
+        position.side, symbol, payload
+    );
 
-        info!("Sell Status = {}, Category = {}", &body, category);
+    let res = client
+        .post("https://api-testnet.bybit.com/v5/order/create")
+        .headers(construct_headers(&payload, recv_window))
+        .body(payload)
+        .send()
+        .await?;
+    let body = res.text().await?;
+
+    info!("Sweep Status = {}", &body);
+    // A rejection comes back with a retCode and no orderId
+    match serde_json::from_str::<OrderResponse>(&body) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Sweep of {} not taken", symbol).into()),
     }
+}
+
+// Exchange side TP/SL on the whole linear position, so it still gets closed if the process dies
+// before the ladder finishes. It's replaced on every call and goes away with the position, a
+// missing price is sent as "0" to clear it. Spot has no position to attach it to and stays on
+// the in-process exits
+async fn set_trading_stop(
+    client: Client,
+    symbol: &str,
+    symbol_rules: &InstrumentRules,
+    protection: Protection,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let format_price = |price: Option<Decimal>| {
+        price
+            .map(|price| symbol_rules.format_price(price))
+            .unwrap_or(String::from("0"))
+    };
+    let payload = format!(
+        r#"{{"category":"linear","symbol":"{}","tpslMode":"Full","positionIdx":0,"takeProfit":"{}","stopLoss":"{}","tpTriggerBy":"MarkPrice","slTriggerBy":"MarkPrice"}}"#,
+        symbol,
+        format_price(protection.target_price),
+        format_price(protection.stop_price)
+    );
+    let response = post_position_request(
+        client,
+        "https://api-testnet.bybit.com/v5/position/trading-stop",
+        payload,
+        recv_window,
+    )
+    .await?;
+    info!(
+        "Trading stop for {}: {} {}",
+        symbol, response.retCode, response.retMsg
+    );
     Ok(())
 }
 
-async fn get_leverage(
+async fn get_position(
     client: Client,
     symbol: &str,
     recv_window: &str,
-) -> Result<f32, Box<dyn error::Error>> {
+) -> Result<Option<position_list::LeverageList>, Box<dyn error::Error>> {
     let params = format!("category=linear&symbol={}", symbol);
     let url = format!("https://api-testnet.bybit.com/v5/position/list?{}", params);
     let res = client
@@ -212,25 +1061,135 @@ async fn get_leverage(
     let body = res.text().await?;
 
     let leverage_json: PositionList = serde_json::from_str(&body).unwrap_or(PositionList {
-        result: position_list::Result {
-            list: vec![position_list::LeverageList {
-                leverage: "0.0".to_string(),
-            }],
-        },
+        result: position_list::Result { list: vec![] },
     });
 
-    let value: f32 = leverage_json.result.list[0]
-        .leverage
-        .parse()
-        .expect("Issue parsing the leverage to f32");
+    Ok(leverage_json.result.list.into_iter().next())
+}
+
+async fn get_account_margin_mode(
+    client: Client,
+    recv_window: &str,
+) -> Result<Option<MarginMode>, Box<dyn error::Error>> {
+    let res = client
+        .get("https://api-testnet.bybit.com/v5/account/info")
+        .headers(construct_headers("", recv_window))
+        .send()
+        .await?;
+    let body = res.text().await?;
+    let account_info: AccountInfo = match serde_json::from_str(&body) {
+        Ok(account_info) => account_info,
+        Err(e) => {
+            error!("Failed to parse the account info: {} {}", e, body);
+            return Ok(None);
+        }
+    };
 
-    Ok(value)
+    Ok(MarginMode::from_account_margin_mode(
+        &account_info.result.marginMode,
+    ))
 }
 
-async fn get_price(client: Client, symbol: &str) -> Result<f32, Box<dyn error::Error>> {
+// Leverage of the symbol's position and the margin mode of the account, which is where a unified
+// account keeps it
+async fn get_position_setup(
+    client: Client,
+    symbol: &str,
+    recv_window: &str,
+) -> Result<(Decimal, Option<MarginMode>), Box<dyn error::Error>> {
+    let (position, margin_mode) = futures::join!(
+        get_position(client.clone(), symbol, recv_window),
+        get_account_margin_mode(client, recv_window)
+    );
+    let leverage: Decimal = position?
+        .and_then(|position| position.leverage.parse().ok())
+        .unwrap_or_default();
+
+    Ok((leverage, margin_mode?))
+}
+
+async fn post_position_request(
+    client: Client,
+    url: &str,
+    payload: String,
+    recv_window: &str,
+) -> Result<ApiResponse, Box<dyn error::Error>> {
+    let res = client
+        .post(url)
+        .headers(construct_headers(&payload, recv_window))
+        .body(payload)
+        .send()
+        .await?;
+    let body = res.text().await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+// Applies the margin mode and leverage of the case, then reads them back from the account and
+// the position so the entry can be sized off what the account actually uses. The margin mode is
+// the whole account's, so it's only switched when it differs
+async fn apply_futures_setup(
+    client: Client,
+    symbol: &str,
+    futures_setup: FuturesSetup,
+    recv_window: &str,
+) -> Result<(Decimal, Option<MarginMode>), Box<dyn error::Error>> {
+    if get_account_margin_mode(client.clone(), recv_window).await?
+        != Some(futures_setup.margin_mode)
+    {
+        let payload = format!(
+            r#"{{"setMarginMode":"{}"}}"#,
+            futures_setup.margin_mode.as_account_margin_mode()
+        );
+        let response = post_position_request(
+            client.clone(),
+            "https://api-testnet.bybit.com/v5/account/set-margin-mode",
+            payload,
+            recv_window,
+        )
+        .await?;
+        if response.retCode != 0 {
+            error!(
+                "Failed to set margin mode for {}: {}",
+                symbol, response.retMsg
+            );
+        }
+    }
+
+    let payload = format!(
+        r#"{{"category":"linear","symbol":"{}","buyLeverage":"{}","sellLeverage":"{}"}}"#,
+        symbol, futures_setup.leverage, futures_setup.leverage
+    );
+    let response = post_position_request(
+        client.clone(),
+        "https://api-testnet.bybit.com/v5/position/set-leverage",
+        payload,
+        recv_window,
+    )
+    .await?;
+    // 110043 means the leverage is already set
+    if response.retCode != 0 && response.retCode != 110043 {
+        error!("Failed to set leverage for {}: {}", symbol, response.retMsg);
+    }
+
+    get_position_setup(client, symbol, recv_window).await
+}
+
+async fn get_price(
+    client: Client,
+    symbol: &str,
+    category: &str,
+) -> Result<Decimal, Box<dyn error::Error>> {
+    Ok(get_price_quote(client, symbol, category).await?.price)
+}
+
+async fn get_price_quote(
+    client: Client,
+    symbol: &str,
+    category: &str,
+) -> Result<PriceQuote, Box<dyn error::Error>> {
     let url = format!(
-        "https://api-testnet.bybit.com/v5/market/tickers?category=linear&symbol={}",
-        symbol
+        "https://api-testnet.bybit.com/v5/market/tickers?category={}&symbol={}",
+        category, symbol
     );
     let res = client.get(&url).send().await?;
     let body = res.text().await?;
@@ -242,95 +1201,211 @@ async fn get_price(client: Client, symbol: &str) -> Result<f32, Box<dyn error::E
                     lastPrice: "0.0".to_string(),
                 }],
             },
+            time: 0,
         });
 
-    let value: f32 = price_information.result.list[0]
-        .lastPrice
-        .parse()
-        .expect("Issue parsing the price");
+    let price: Decimal = price_information
+        .result
+        .list
+        .first()
+        .and_then(|ticker| ticker.lastPrice.parse().ok())
+        .unwrap_or_default();
+
+    Ok(PriceQuote {
+        price,
+        time: price_information.time,
+    })
+}
+
+// The first of the ticker's candidate instruments the venue trades, one request each, and the
+// ticker as announced with no rules when it trades none
+async fn resolve_instrument(
+    client: Client,
+    ticker: &str,
+    naming: &VenueNaming,
+    category: &str,
+) -> Result<(ResolvedSymbol, Option<InstrumentRules>), Box<dyn error::Error>> {
+    let mut candidates = symbol_resolution::candidates(ticker, naming);
+    for (index, candidate) in candidates.iter().enumerate() {
+        if let Some(rules) =
+            get_instrument_rules(client.clone(), &candidate.symbol, category).await?
+        {
+            return Ok((candidates.swap_remove(index), Some(rules)));
+        }
+    }
+    Ok((candidates.swap_remove(0), None))
+}
+
+// Rules of every instrument of the category that can be traded, by symbol
+async fn get_symbols_rules(
+    client: Client,
+    category: &str,
+) -> Result<HashMap<String, InstrumentRules>, Box<dyn error::Error>> {
+    let mut symbols_rules = HashMap::new();
+    let mut cursor = String::new();
+    loop {
+        let res = client
+            .get("https://api-testnet.bybit.com/v5/market/instruments-info")
+            .query(&[
+                ("category", category),
+                ("limit", "1000"),
+                ("cursor", cursor.as_str()),
+            ])
+            .send()
+            .await?;
+        let symbol_information: SymbolInformation = serde_json::from_str(&res.text().await?)?;
+        symbols_rules.extend(
+            symbol_information
+                .result
+                .list
+                .into_iter()
+                .filter(|instrument| instrument.status == "Trading")
+                .map(|instrument| {
+                    (
+                        instrument.symbol.clone(),
+                        instrument_rules(instrument, category),
+                    )
+                }),
+        );
+        cursor = symbol_information.result.nextPageCursor;
+        if cursor.is_empty() {
+            return Ok(symbols_rules);
+        }
+    }
+}
 
-    Ok(value)
+// Both instrument lists at once, so the aliases are never refreshed from half of them
+async fn get_instrument_lists(
+    client: Client,
+) -> Result<
+    (
+        HashMap<String, InstrumentRules>,
+        HashMap<String, InstrumentRules>,
+    ),
+    Box<dyn error::Error>,
+> {
+    Ok((
+        get_symbols_rules(client.clone(), "linear").await?,
+        get_symbols_rules(client, "spot").await?,
+    ))
 }
 
-async fn get_symbol_information(
+async fn get_instrument_rules(
     client: Client,
     symbol: &str,
-) -> Result<f32, Box<dyn error::Error>> {
+    category: &str,
+) -> Result<Option<InstrumentRules>, Box<dyn error::Error>> {
     let url = format!(
-        "https://api-testnet.bybit.com/v5/market/instruments-info?category=linear&symbol={}",
-        symbol
+        "https://api-testnet.bybit.com/v5/market/instruments-info?category={}&symbol={}",
+        category, symbol
     );
     let res = client.get(&url).send().await?;
     let body = res.text().await?;
 
-    let symbol_information: SymbolInformation = serde_json::from_str(&body)?;
+    let symbol_information: SymbolInformation = match serde_json::from_str(&body) {
+        Ok(symbol_information) => symbol_information,
+        Err(e) => {
+            error!(
+                "Failed to parse {} instrument {}: {} {}",
+                category, symbol, e, body
+            );
+            return Ok(None);
+        }
+    };
 
-    let value: f32 = symbol_information.result.list[0]
-        .lotSizeFilter
-        .qtyStep
-        .parse()
-        .expect("Issue parsing the qty step");
+    let instrument = match symbol_information.result.list.into_iter().next() {
+        Some(instrument) => instrument,
+        None => return Ok(None),
+    };
+    // No rules for an instrument that can't be traded, so no leg is opened on it
+    if instrument.status != "Trading" {
+        info!("{} {} is {}", category, symbol, instrument.status);
+        return Ok(None);
+    }
+    Ok(Some(instrument_rules(instrument, category)))
+}
 
-    Ok(value)
+fn instrument_rules(instrument: Symbol, category: &str) -> InstrumentRules {
+    let lot_size = instrument.lotSizeFilter;
+    let price_filter = instrument.priceFilter;
+    match category {
+        "spot" => InstrumentRules {
+            base_asset: instrument.baseCoin,
+            step_size: lot_size.basePrecision.parse().unwrap_or_default(),
+            min_qty: lot_size.minOrderQty.parse().unwrap_or_default(),
+            max_qty: lot_size.maxOrderQty.parse().unwrap_or_default(),
+            min_notional: lot_size.minOrderAmt.parse().unwrap_or_default(),
+            max_notional: lot_size.maxOrderAmt.parse().unwrap_or_default(),
+            quote_step: lot_size.quotePrecision.parse().unwrap_or_default(),
+            tick_size: price_filter.tickSize.parse().unwrap_or_default(),
+            ..Default::default()
+        },
+        _ => InstrumentRules {
+            base_asset: instrument.baseCoin,
+            step_size: lot_size.qtyStep.parse().unwrap_or_default(),
+            min_qty: lot_size.minOrderQty.parse().unwrap_or_default(),
+            max_qty: lot_size.maxOrderQty.parse().unwrap_or_default(),
+            market_max_qty: lot_size.maxMktOrderQty.parse().unwrap_or_default(),
+            min_notional: lot_size.minNotionalValue.parse().unwrap_or_default(),
+            tick_size: price_filter.tickSize.parse().unwrap_or_default(),
+            min_price: price_filter.minPrice.parse().unwrap_or_default(),
+            max_price: price_filter.maxPrice.parse().unwrap_or_default(),
+            ..Default::default()
+        },
+    }
 }
 
-async fn get_order_qty(
+// A fresh order can take a moment to show up in the order history and to finish filling
+const ORDER_HISTORY_ATTEMPTS: u32 = 5;
+
+// The spot order once it is done filling, as the order history has it
+async fn get_spot_fill(
     client: Client,
     order_id: &str,
     recv_window: &str,
-) -> Result<f32, Box<dyn error::Error>> {
-    let params = format!("category=spot&order_id={}", order_id);
+) -> Result<OrderInformationList, Box<dyn error::Error>> {
+    let params = format!("category=spot&orderId={}", order_id);
     let url = format!("https://api-testnet.bybit.com/v5/order/history?{}", params);
-    let res = client
-        .get(&url)
-        .headers(construct_headers(&params, recv_window))
-        .send()
-        .await?;
-    let body = res.text().await?;
-
-    let order_json: OrderInformation = serde_json::from_str(&body)?;
+    for _ in 0..ORDER_HISTORY_ATTEMPTS {
+        let res = client
+            .get(&url)
+            .headers(construct_headers(&params, recv_window))
+            .send()
+            .await?;
+        let body = res.text().await?;
 
-    let qty: f32 = order_json.result.list[0]
-        .qty
-        .parse()
-        .expect("Issue parsing the qty");
-
-    Ok(qty)
-}
-
-fn title_case(title: &str) -> Result<(&str, TpCases), Box<dyn error::Error>> {
-    if title.contains("Binance Will List") {
-        Ok((r#"\([\d]*([^()]+)\)"#, TpCases::BinanceListing))
-    } else if title.contains("마켓 디지털 자산 추가") {
-        Ok((r#"[\( ](\w*)[,\)]"#, TpCases::UpbitListing))
-    } else if title.contains("Binance Futures Will Launch USDⓈ-M") {
-        Ok((
-            r#"(?<=USDⓈ-M )\d*(.*)(?= Perpetual)"#,
-            TpCases::BinanceFuturesListing,
-        ))
-    } else if title.contains("원화 마켓 추가") {
-        Ok((r#"\([\d]*([^()]+)\)"#, TpCases::BithumbListing))
-    } else {
-        Ok(("", TpCases::NoListing))
+        let order_json: OrderInformation = serde_json::from_str(&body)
+            .map_err(|e| format!("unreadable order history {}: {}", e, body))?;
+        if let Some(order) = order_json.result.list.into_iter().next() {
+            if !matches!(order.orderStatus.as_str(), "New" | "PartiallyFilled") {
+                return Ok(order);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
     }
+    Err(format!("order {} is not done in the order history", order_id).into())
 }
 
-fn process_title(title: &str) -> Result<(Vec<&str>, TpCases), Box<dyn error::Error>> {
-    let (re_string, tp_case) = title_case(title)?;
-    if tp_case == TpCases::NoListing {
-        return Ok((vec![""], tp_case));
-    }
-    let re = Regex::new(re_string)?;
-    let symbols = re
-        .captures_iter(title)
-        .flatten()
-        .map(|m| m.get(1).expect("There was no group found"))
-        .map(|m| m.as_str())
-        .collect();
+// Spot buys pay the fee in the bought coin, so the sellable amount is the filled qty minus it
+fn spot_filled_base_qty(order: &OrderInformationList) -> Decimal {
+    let filled_qty: Decimal = order.cumExecQty.parse().unwrap_or_default();
+    let fee: Decimal = order.cumExecFee.parse().unwrap_or_default();
+    filled_qty - fee
+}
 
-    info!("Symbol: {:?}", symbols);
+// Tickers of the first rule that matches the title, nothing if it isn't a listing
+fn process_title<'a>(
+    title_rules: &'a TitleRules,
+    title: &'a str,
+) -> (Vec<&'a str>, Option<&'a TitleRule>) {
+    let (title_rule, symbols) = match title_rules.classify(title) {
+        Some(classified) => classified,
+        None => return (Vec::new(), None),
+    };
 
-    Ok((symbols, tp_case))
+    info!("Symbol: {:?} from rule {}", symbols, title_rule.name);
+
+    (symbols, Some(title_rule))
 }
 
 fn construct_headers(payload: &str, recv_window: &str) -> HeaderMap {
@@ -378,6 +1453,58 @@ fn construct_headers(payload: &str, recv_window: &str) -> HeaderMap {
     headers
 }
 
+// Sets how long after the private stream drops the exchange cancels every open order of the
+// product. It only applies while a private stream is connected
+async fn set_disconnect_cancel_all(
+    client: Client,
+    product: &str,
+    recv_window: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let payload = format!(
+        r#"{{"product":"{}","timeWindow":{}}}"#,
+        product,
+        health::DEAD_MAN_WINDOW.as_secs()
+    );
+    let response = post_position_request(
+        client,
+        "https://api-testnet.bybit.com/v5/order/disconnected-cancel-all",
+        payload,
+        recv_window,
+    )
+    .await?;
+    info!(
+        "Set disconnect cancel all for {}: {} {}",
+        product, response.retCode, response.retMsg
+    );
+    Ok(())
+}
+
+// Waits for the step while the exits keep running, for the time between feed connections. None
+// when SIGINT or SIGTERM comes in first
+async fn alongside_exits<T, F>(
+    step: impl Future<Output = T>,
+    exits: &mut FuturesUnordered<F>,
+    interrupt: &mut Signal,
+    terminate: &mut Signal,
+) -> Option<T>
+where
+    F: Future<Output = Result<(), Box<dyn error::Error>>>,
+{
+    tokio::pin!(step);
+    loop {
+        tokio::select! {
+            output = &mut step => return Some(output),
+            _ = interrupt.recv() => return None,
+            _ = terminate.recv() => return None,
+            Some(result) = exits.next(), if !exits.is_empty() => {
+                if let Err(e) = result {
+                    error!("Failed to process trade pair: {}", e);
+                }
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
     let args: Vec<String> = env::args().collect();
@@ -386,66 +1513,236 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
         .filter(None, log::LevelFilter::Info)
         .init();
     let client = Client::new();
-    let size_future: f32 = args
+    // `rules test "<title>"` checks a headline against the rules file and exits
+    if args.get(1).is_some_and(|arg| arg == "rules") {
+        let title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)?;
+        let title = match (args.get(2).map(String::as_str), args.get(3)) {
+            (Some("test"), Some(title)) => title,
+            _ => return Err("Usage: rules test \"<title>\"".into()),
+        };
+        match process_title(&title_rules, title) {
+            (symbols, Some(title_rule)) => info!(
+                "Rule {} matches as {:?}: {:?} on {:?}",
+                title_rule.name, title_rule.case, symbols, title_rule.venues
+            ),
+            (_, None) => info!("None of the {} rules match", title_rules.rules().len()),
+        }
+        return Ok(());
+    }
+    // `flatten [recv_window]` runs the kill switch and exits
+    if args.get(1).is_some_and(|arg| arg == "flatten") {
+        let recv_window = args.get(2).map(String::as_str).unwrap_or("1000");
+        return flatten_all(client, recv_window).await;
+    }
+    let size_future: Decimal = args
         .get(1)
         .expect("Input size for futures")
         .parse()
-        .unwrap_or(0.0);
-    let size_spot: f32 = args
+        .unwrap_or_default();
+    let size_spot: Decimal = args
         .get(2)
         .unwrap_or(&String::from("0.0"))
         .parse()
-        .unwrap_or(0.0);
+        .unwrap_or_default();
     let default_recv_window = &String::from("1000");
     let recv_window: &str = args.get(3).unwrap_or(default_recv_window);
-    let mut tp_map = HashMap::new();
-    tp_map.insert(
-        TpCases::BinanceListing,
-        [
-            //change the time to 2 * 60
-            TpInstance {
-                time: 30,
-                pct: 0.75,
-            },
-            // 8 * 60
-            TpInstance {
-                time: 45,
-                pct: 0.25,
-            },
-        ],
+    let orphan_policy = args
+        .get(4)
+        .map(|arg| OrphanPolicy::from_arg(arg).expect("Orphan policy is report, adopt or flatten"))
+        .unwrap_or(OrphanPolicy::Report);
+    let shutdown_policy = args
+        .get(5)
+        .map(|arg| {
+            ShutdownPolicy::from_arg(arg)
+                .expect("Shutdown policy is flatten, persist, wait or wait:<seconds>")
+        })
+        .unwrap_or(ShutdownPolicy::Persist);
+    let mut title_rules = TitleRules::load(TITLE_RULES_PATH, TITLE_RULE_VENUES)?;
+    let mut ticker_aliases = TickerAliases::load(TICKER_ALIASES_PATH)?;
+    // The feed only reads these, they are fetched again in the background
+    let (mut linear_rules, mut spot_rules) = get_instrument_lists(client.clone()).await?;
+    ticker_aliases.refresh(
+        linear_rules
+            .values()
+            .chain(spot_rules.values())
+            .map(|rules| rules.base_asset.as_str()),
     );
-    tp_map.insert(
-        TpCases::UpbitListing,
-        [
-            TpInstance { time: 2 * 60, pct: 0.75 },
-            TpInstance {
-                time: 13 * 60,
-                pct: 0.25,
-            },
-        ],
-    );
-    tp_map.insert(
-        TpCases::BinanceFuturesListing,
-        [
-            TpInstance { time: 7, pct: 0.5 },
-            TpInstance {
-                time: 2 * 60,
-                pct: 0.5,
-            },
-        ],
+    info!(
+        "Loaded {} title rules from {}",
+        title_rules.rules().len(),
+        TITLE_RULES_PATH
     );
-    tp_map.insert(
-        TpCases::BithumbListing,
-        [
-            TpInstance { time: 90, pct: 1.0 },
-            TpInstance { time: 0, pct: 0.0 },
-        ],
-    );
-    loop {
+    // Orphaned positions get a short ladder behind a stop, since nothing is known about why they
+    // were opened
+    let adopt_ladder = ExitLadder::new(vec![TpInstance {
+        time: 5 * 60,
+        pct: Decimal::from("1"),
+        basis: TpBasis::Original,
+        price_target: None,
+    }])
+    .and_then(|exit_ladder| exit_ladder.with_stop_loss(Decimal::from("0.1")))
+    .expect("Invalid exit ladder for adopted positions");
+
+    // Exits run next to the feed, so a resumed or long ladder never holds up the next listing
+    let mut exits = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
+    for (pending_exit, symbol_rules) in resume_exits(client.clone(), recv_window).await {
+        let claim = match LegClaim::claim(&pending_exit.symbol, &pending_exit.category) {
+            Ok(claim) => claim,
+            Err(e) => {
+                error!("Not running the exit of {}: {}", pending_exit.symbol, e);
+                continue;
+            }
+        };
+        let client = client.clone();
+        exits.push(Box::pin(claim.hold(async move {
+            market_sell_position(client, pending_exit, &symbol_rules, recv_window).await
+        })));
+    }
+    for scheduled_buy in exit_schedule::load_scheduled_buys(SCHEDULED_BUYS_PATH) {
+        info!("Resuming the scheduled buy of {}", scheduled_buy.ticker);
+        exits.push(Box::pin(scheduled_buy_spot_position(
+            client.clone(),
+            scheduled_buy,
+            recv_window,
+        )));
+    }
+    match reconcile_orphans(client.clone(), orphan_policy, &adopt_ladder, recv_window).await {
+        Ok(adopted) => {
+            for (pending_exit, symbol_rules) in adopted {
+                let claim = match LegClaim::claim(&pending_exit.symbol, &pending_exit.category) {
+                    Ok(claim) => claim,
+                    Err(e) => {
+                        error!("Not running the exit of {}: {}", pending_exit.symbol, e);
+                        continue;
+                    }
+                };
+                let client = client.clone();
+                exits.push(Box::pin(claim.hold(async move {
+                    market_sell_position(client, pending_exit, &symbol_rules, recv_window).await
+                })));
+            }
+        }
+        Err(e) => error!("Failed to reconcile orphaned positions: {}", e),
+    }
+    // SIGUSR1 runs the kill switch without stopping the process
+    let mut kill_signal = signal(SignalKind::user_defined1())?;
+    let mut halted = false;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    for product in ["DERIVATIVES", "SPOT"] {
+        if let Err(e) = set_disconnect_cancel_all(client.clone(), product, recv_window).await {
+            error!("Failed to set disconnect cancel all for {}: {}", product, e);
+        }
+    }
+    // The private stream is only kept alive while the feed answers and no order is stuck, so a
+    // hung bot has its orders cancelled by the exchange
+    let mut private_stream: Option<PrivateStream> = None;
+    let mut heartbeat = tokio::time::interval(health::HEARTBEAT);
+    let mut healthy = true;
+    let mut instrument_refresh_tick = tokio::time::interval(INSTRUMENT_REFRESH);
+    let mut instrument_refresh = FuturesUnordered::new();
+    let mut reconnect_delay = RECONNECT_DELAY_MIN;
+    'feed: loop {
         //wss://news.treeofalpha.com/ws ws://35.73.200.147:5050
-        if let Ok((mut socket, _)) = connect_async("wss://news.treeofalpha.com/ws").await {
-            while let Some(msg) = socket.next().await {
-                let msg = msg.unwrap_or(Message::binary(Vec::new()));
+        let connected = match alongside_exits(
+            connect_async("wss://news.treeofalpha.com/ws"),
+            &mut exits,
+            &mut interrupt,
+            &mut terminate,
+        )
+        .await
+        {
+            Some(connected) => connected,
+            None => break 'feed,
+        };
+        if let Ok((mut socket, _)) = connected {
+            reconnect_delay = RECONNECT_DELAY_MIN;
+            let mut last_feed_frame = Instant::now();
+            loop {
+                let msg = tokio::select! {
+                    msg = socket.next() => msg,
+                    Some(result) = exits.next(), if !exits.is_empty() => {
+                        if let Err(e) = result {
+                            error!("Failed to process trade pair: {}", e);
+                        }
+                        continue;
+                    }
+                    _ = instrument_refresh_tick.tick(), if instrument_refresh.is_empty() => {
+                        instrument_refresh.push(Box::pin(get_instrument_lists(client.clone())));
+                        continue;
+                    }
+                    // The lists in use are kept when a refresh fails, rather than taking every
+                    // ticker as unlisted
+                    Some(refreshed) = instrument_refresh.next(),
+                        if !instrument_refresh.is_empty() =>
+                    {
+                        match refreshed {
+                            Ok((linear, spot)) => {
+                                linear_rules = linear;
+                                spot_rules = spot;
+                                ticker_aliases.refresh(
+                                    linear_rules
+                                        .values()
+                                        .chain(spot_rules.values())
+                                        .map(|rules| rules.base_asset.as_str()),
+                                );
+                            }
+                            Err(e) => error!("Failed to refresh the instrument lists: {}", e),
+                        }
+                        continue;
+                    }
+                    _ = heartbeat.tick() => {
+                        let now_healthy = health::is_healthy(
+                            Instant::now(),
+                            last_feed_frame,
+                            health::busy_since(),
+                        );
+                        if !now_healthy {
+                            if healthy {
+                                error!("Unhealthy, dropping the private stream");
+                            }
+                            private_stream = None;
+                        } else if let Some(stream) = private_stream.as_mut() {
+                            if !stream.ping().await {
+                                error!("Private stream stopped answering, reconnecting");
+                                private_stream = None;
+                            }
+                        } else {
+                            private_stream = private_stream::connect_private_stream().await;
+                        }
+                        healthy = now_healthy;
+                        // The pong is what keeps the feed counted as alive
+                        if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    _ = interrupt.recv() => break 'feed,
+                    _ = terminate.recv() => break 'feed,
+                    _ = kill_signal.recv() => {
+                        // Running exits would put their protection back, so they go first
+                        exits.clear();
+                        if let Err(e) = flatten_all(
+                            client.clone(),
+                            recv_window,
+                        )
+                        .await
+                        {
+                            error!("Failed to flatten everything: {}", e);
+                        }
+                        halted = true;
+                        error!("Halted, no new trades until restart");
+                        continue;
+                    }
+                };
+                let msg = match msg {
+                    Some(Ok(msg)) => {
+                        last_feed_frame = Instant::now();
+                        msg
+                    }
+                    Some(Err(_)) => Message::binary(Vec::new()),
+                    None => break,
+                };
 
                 if msg.is_text() {
                     let response = msg.to_text()?;
@@ -461,42 +1758,143 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
                         }
                     };
 
-                    let (symbols, tp_case) = process_title(&tree_response.title)?;
+                    title_rules.reload_if_changed();
+                    ticker_aliases.reload_if_changed();
+                    let (symbols, title_rule) = process_title(&title_rules, &tree_response.title);
 
-                    if tp_case != TpCases::NoListing {
-                        let mut handles =
-                            FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
+                    if halted && title_rule.is_some() {
+                        info!("Halted, not trading {}", &tree_response.title);
+                    } else if let Some(title_rule) = title_rule {
+                        let size_future = size_future * title_rule.size_scale;
+                        let size_spot = size_spot * title_rule.size_scale;
+                        let listing_time = title_rules
+                            .listing_time(&tree_response.title)
+                            .or_else(|| title_rules.listing_time(&tree_response.body));
                         for symbol in symbols.iter() {
                             info!("symbol = {}", symbol);
+                            if let Err(e) = pre_trade::check_ticker(symbol) {
+                                error!("Skipping {}: {}", symbol, e);
+                                continue;
+                            }
+                            let symbol = match ticker_aliases.resolve(symbol) {
+                                Ok(asset) => {
+                                    if asset != *symbol {
+                                        info!("{} trades as {}", symbol, asset);
+                                    }
+                                    asset
+                                }
+                                // A coin bought at its listing time isn't on any venue yet
+                                Err(_) if title_rule.spot_entry == SpotEntry::AtListingTime => {
+                                    symbol
+                                }
+                                Err(e) => {
+                                    error!("Unresolvable ticker {}: {}", symbol, e);
+                                    continue;
+                                }
+                            };
 
-                            let trade_pair = format!("{}USDT", symbol);
-
-                            let tp_instance_arr = tp_map.get(&tp_case).unwrap_or(&EMPTY_TP_CASE);
-
-                            let qty_step =
-                                get_symbol_information(client.clone(), &trade_pair).await?;
-
-                            handles.push(Box::pin(market_buy_futures_position(
-                                client.clone(),
-                                trade_pair.clone(),
-                                size_future,
-                                qty_step,
-                                tp_instance_arr,
-                                recv_window,
-                            )));
-
-                            handles.push(Box::pin(market_buy_spot_position(
-                                client.clone(),
-                                trade_pair.clone(),
-                                size_spot,
-                                qty_step,
-                                tp_instance_arr,
-                                recv_window,
-                            )));
-                        }
-                        while let Some(result) = handles.next().await {
-                            if let Err(e) = result {
-                                error!("Failed to process trade pair: {}", e);
+                            if title_rule.trades_on("linear") {
+                                let futures_symbol =
+                                    symbol_resolution::resolve(symbol, &BYBIT_LINEAR, |symbol| {
+                                        linear_rules.contains_key(symbol)
+                                    });
+                                match pre_trade::check_size(size_future)
+                                    .and_then(|_| {
+                                        pre_trade::check_instrument(
+                                            linear_rules.get(&futures_symbol.symbol),
+                                        )
+                                    })
+                                    .and_then(|futures_rules| {
+                                        Ok((
+                                            futures_rules,
+                                            LegClaim::claim(&futures_symbol.symbol, "linear")?,
+                                        ))
+                                    }) {
+                                    Ok((futures_rules, claim)) => {
+                                        log_resolved(symbol, &futures_symbol);
+                                        exits.push(Box::pin(claim.hold(
+                                            market_open_futures_position(
+                                                client.clone(),
+                                                futures_symbol.symbol.clone(),
+                                                size_future,
+                                                futures_rules,
+                                                title_rule.futures_setup,
+                                                title_rule.exit_ladder.clone(),
+                                                recv_window,
+                                            ),
+                                        )))
+                                    }
+                                    Err(e) => error!(
+                                        "Skipping futures leg {}: {}",
+                                        futures_symbol.symbol, e
+                                    ),
+                                }
+                            }
+
+                            if title_rule.trades_on("spot")
+                                && title_rule.spot_entry == SpotEntry::AtListingTime
+                            {
+                                match pre_trade::check_size(size_spot).and_then(|_| {
+                                    listing_time.ok_or_else(|| {
+                                        "no listing time in the announcement".to_string()
+                                    })
+                                }) {
+                                    Ok(listing_time) => {
+                                        let scheduled_buy = ScheduledBuy {
+                                            ticker: symbol.to_string(),
+                                            unit_coin_qty: size_spot,
+                                            listing_time_ms: listing_time.timestamp_millis(),
+                                            exit_ladder: title_rule.exit_ladder.clone(),
+                                        };
+                                        if let Err(e) = exit_schedule::save_scheduled_buy(
+                                            SCHEDULED_BUYS_PATH,
+                                            &scheduled_buy,
+                                        ) {
+                                            error!(
+                                                "Failed to save the scheduled buy of {}: {}",
+                                                symbol, e
+                                            );
+                                        }
+                                        exits.push(Box::pin(scheduled_buy_spot_position(
+                                            client.clone(),
+                                            scheduled_buy,
+                                            recv_window,
+                                        )))
+                                    }
+                                    Err(e) => error!("Skipping spot leg {}: {}", symbol, e),
+                                }
+                            } else if title_rule.trades_on("spot") {
+                                let spot_symbol =
+                                    symbol_resolution::resolve(symbol, &BYBIT_SPOT, |symbol| {
+                                        spot_rules.contains_key(symbol)
+                                    });
+                                match pre_trade::check_size(size_spot)
+                                    .and_then(|_| {
+                                        pre_trade::check_instrument(
+                                            spot_rules.get(&spot_symbol.symbol),
+                                        )
+                                    })
+                                    .and_then(|spot_rules| {
+                                        Ok((
+                                            spot_rules,
+                                            LegClaim::claim(&spot_symbol.symbol, "spot")?,
+                                        ))
+                                    }) {
+                                    Ok((spot_rules, claim)) => {
+                                        log_resolved(symbol, &spot_symbol);
+                                        exits.push(Box::pin(claim.hold(market_buy_spot_position(
+                                            client.clone(),
+                                            spot_symbol.symbol.clone(),
+                                            size_spot,
+                                            spot_rules,
+                                            title_rule.exit_ladder.clone(),
+                                            recv_window,
+                                        ))))
+                                    }
+                                    Err(e) => {
+                                        error!("Skipping spot leg {}: {}", spot_symbol.symbol, e)
+                                    }
+                                }
                             }
                         }
                     } else {
@@ -505,7 +1903,59 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
                 }
             }
         } else {
-            error!("Can't connect to test server");
+            error!(
+                "Can't connect to test server, retrying in {:?}",
+                reconnect_delay
+            );
+            if alongside_exits(
+                tokio::time::sleep(reconnect_delay),
+                &mut exits,
+                &mut interrupt,
+                &mut terminate,
+            )
+            .await
+            .is_none()
+            {
+                break 'feed;
+            }
+            reconnect_delay = std::cmp::min(reconnect_delay * 2, RECONNECT_DELAY_MAX);
         };
     }
+
+    // Leaving the feed loop drops the socket, so nothing new opens while the exits are dealt with
+    info!(
+        "Shutting down with {:?}, {} exits running",
+        shutdown_policy,
+        exits.len()
+    );
+    match shutdown_policy {
+        ShutdownPolicy::Flatten => {
+            exits.clear();
+            flatten_all(client.clone(), recv_window).await?;
+        }
+        ShutdownPolicy::Persist => error!(
+            "Open orders are cancelled {}s after the private stream closes, until the exits resume",
+            health::DEAD_MAN_WINDOW.as_secs()
+        ),
+        ShutdownPolicy::Wait(timeout) => {
+            let drain_exits = async {
+                while let Some(result) = exits.next().await {
+                    if let Err(e) = result {
+                        error!("Failed to process trade pair: {}", e);
+                    }
+                }
+            };
+            // A second signal stops the wait
+            tokio::select! {
+                drained = tokio::time::timeout(timeout, drain_exits) => match drained {
+                    Ok(()) => info!("All exits finished"),
+                    Err(_) => error!("Timed out waiting for the exits"),
+                },
+                _ = interrupt.recv() => error!("Stopped waiting for the exits"),
+                _ = terminate.recv() => error!("Stopped waiting for the exits"),
+            }
+        }
+    }
+    info!("Unfinished exits are saved in {}", EXIT_SCHEDULE_PATH);
+    Ok(())
 }
@@ -0,0 +1,20 @@
+use serde::Deserialize;
+//{"retCode":0,"retMsg":"OK","result":{"list":[{"orderId":"fd4300ae-7847-404e-b947-b46980a4d140","orderLinkId":"","symbol":"ETHUSDT","price":"1600.00","qty":"0.10","side":"Buy","orderStatus":"New","orderType":"Limit","stopOrderType":""}],"nextPageCursor":"","category":"linear"},"retExtInfo":{},"time":1684765770483}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct OpenOrders {
+    pub result: Result,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Result {
+    pub list: Vec<OpenOrder>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct OpenOrder {
+    pub symbol: String,
+}
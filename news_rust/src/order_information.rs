@@ -15,7 +15,8 @@ pub struct OrderInformationResult {
 #[allow(non_snake_case)]
 #[derive(Deserialize)]
 pub struct OrderInformationList {
+    pub orderStatus: String,
     pub cumExecQty: String,
+    pub cumExecValue: String,
     pub cumExecFee: String,
-    pub qty: String,
 }
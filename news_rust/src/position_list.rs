@@ -14,7 +14,15 @@ pub struct Result {
 }
 
 #[allow(non_snake_case)]
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct LeverageList {
+    #[serde(default)]
+    pub symbol: String,
     pub leverage: String,
+    #[serde(default)]
+    pub size: String,
+    #[serde(default)]
+    pub side: String,
+    #[serde(default)]
+    pub avgPrice: String,
 }
@@ -5,6 +5,9 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct PriceInformation {
     pub result: PriceInformationResult,
+    // Server time of the response, the tickers don't carry their own timestamp
+    #[serde(default)]
+    pub time: i64,
 }
 
 #[allow(non_snake_case)]
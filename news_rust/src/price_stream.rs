@@ -0,0 +1,128 @@
+use crate::exit_ladder::{price_trigger, ExitTrigger, Side};
+use crate::public_trade::PublicTrade;
+use crate::trailing_stop::TrailingStop;
+
+use fraction::Decimal;
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use tokio::net::TcpStream;
+use tokio::time::{sleep_until, timeout_at, Duration, Instant};
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+
+pub struct PriceStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    last_ping: Instant,
+}
+
+// Bybit drops public connections that go 20 seconds without a ping
+const HEARTBEAT_SECS: u64 = 20;
+
+pub async fn connect_price_stream(symbol: &str, category: &str) -> Option<PriceStream> {
+    let url = format!("wss://stream-testnet.bybit.com/v5/public/{}", category);
+    let subscribe = format!(r#"{{"op":"subscribe","args":["publicTrade.{}"]}}"#, symbol);
+    match connect_async(url).await {
+        Ok((mut socket, _)) => {
+            if let Err(e) = socket.send(Message::text(subscribe)).await {
+                error!(
+                    "Failed to subscribe {} price stream for {}: {}",
+                    category, symbol, e
+                );
+                return None;
+            }
+            info!("Connected {} price stream for {}", category, symbol);
+            Some(PriceStream {
+                socket,
+                last_ping: Instant::now(),
+            })
+        }
+        Err(e) => {
+            error!(
+                "Failed to connect {} price stream for {}: {}",
+                category, symbol, e
+            );
+            None
+        }
+    }
+}
+
+// Latest traded price of the next trade message, None once the stream is closed
+async fn next_price(price_stream: &mut PriceStream) -> Option<Decimal> {
+    let heartbeat = Duration::from_secs(HEARTBEAT_SECS);
+    loop {
+        if price_stream.last_ping.elapsed() >= heartbeat {
+            price_stream
+                .socket
+                .send(Message::text(r#"{"op":"ping"}"#))
+                .await
+                .ok()?;
+            price_stream.last_ping = Instant::now();
+        }
+        let next_ping = price_stream.last_ping + heartbeat;
+        let msg = match timeout_at(next_ping, price_stream.socket.next()).await {
+            Ok(Some(Ok(msg))) => msg,
+            Ok(_) => return None,
+            Err(_) => continue,
+        };
+        if !msg.is_text() {
+            continue;
+        }
+        // Subscription acks and pongs don't carry trades
+        if let Ok(trade) = serde_json::from_str::<PublicTrade>(msg.to_text().ok()?) {
+            if let Some(price) = trade.data.last().and_then(|data| data.p.parse().ok()) {
+                return Some(price);
+            }
+        }
+    }
+}
+
+// Waits for the deadline, the target or either stop, whichever comes first. A dropped stream
+// falls back to the timer so the exit still happens
+pub async fn wait_for_exit(
+    price_stream: &mut Option<PriceStream>,
+    entry_time: Instant,
+    deadline: Instant,
+    target_price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    trailing_stop: &mut Option<TrailingStop>,
+    side: Side,
+) -> ExitTrigger {
+    let watch_price = target_price.is_some() || stop_price.is_some() || trailing_stop.is_some();
+    loop {
+        let socket = match price_stream {
+            Some(socket) if watch_price => socket,
+            _ => {
+                sleep_until(deadline).await;
+                return ExitTrigger::Time;
+            }
+        };
+        let price = tokio::select! {
+            _ = sleep_until(deadline) => return ExitTrigger::Time,
+            price = next_price(socket) => price,
+        };
+        match price {
+            Some(price) => {
+                if let Some(trigger) = price_trigger(price, target_price, stop_price, side) {
+                    info!("{:?} hit at {}", trigger, price);
+                    return trigger;
+                }
+                if let Some(trailing_stop) = trailing_stop.as_mut() {
+                    let elapsed_ms = entry_time.elapsed().as_millis() as u64;
+                    if trailing_stop.update(price, elapsed_ms) {
+                        info!(
+                            "Trailing stop hit at {}, peak {}",
+                            price,
+                            trailing_stop.peak()
+                        );
+                        return ExitTrigger::TrailingStop;
+                    }
+                }
+            }
+            None => {
+                error!("Price stream closed, falling back to the timer");
+                *price_stream = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use futures::{SinkExt, StreamExt};
+use hmac::Mac;
+use log::{error, info};
+use std::env;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+
+// Disconnect cancel all only covers orders while an authenticated private connection is open,
+// dropping it starts the exchange's countdown
+pub struct PrivateStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn connect_private_stream() -> Option<PrivateStream> {
+    let api_key = env::var("testnet_bybit_order_key").expect("BYBIT_API_KEY not set");
+    let api_secret = env::var("testnet_bybit_order_secret").expect("BYBIT_API_SECRET not set");
+    let expires = chrono::Utc::now().timestamp_millis() + 10_000;
+    let signature = {
+        type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+        let mut mac = HmacSha256::new_from_slice(api_secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(format!("GET/realtime{}", expires).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    };
+    let auth = format!(
+        r#"{{"op":"auth","args":["{}",{},"{}"]}}"#,
+        api_key, expires, signature
+    );
+    let mut private_stream = match connect_async("wss://stream-testnet.bybit.com/v5/private").await
+    {
+        Ok((socket, _)) => PrivateStream { socket },
+        Err(e) => {
+            error!("Failed to connect private stream: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = private_stream.socket.send(Message::text(auth)).await {
+        error!("Failed to authenticate private stream: {}", e);
+        return None;
+    }
+    let authenticated = private_stream
+        .reply("auth")
+        .await
+        .is_some_and(|reply| reply["success"] == true);
+    if !authenticated {
+        error!("Private stream rejected the authentication");
+        return None;
+    }
+    info!("Connected private stream");
+    Some(private_stream)
+}
+
+impl PrivateStream {
+    // The pong is what proves the connection is still there, false means it should be dropped
+    pub async fn ping(&mut self) -> bool {
+        if self
+            .socket
+            .send(Message::text(r#"{"op":"ping"}"#))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        self.reply("pong").await.is_some()
+    }
+
+    // The next message for the op, {"op":"auth","success":true,..} after authenticating and
+    // {"op":"pong",..} after a ping. None if it doesn't come in time
+    async fn reply(&mut self, op: &str) -> Option<serde_json::Value> {
+        loop {
+            let msg = timeout(REPLY_TIMEOUT, self.socket.next())
+                .await
+                .ok()??
+                .ok()?;
+            let reply: serde_json::Value = match msg.to_text().map(serde_json::from_str) {
+                Ok(Ok(reply)) => reply,
+                _ => continue,
+            };
+            if reply["op"] == op {
+                return Some(reply);
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+//{"topic":"publicTrade.BTCUSDT","type":"snapshot","ts":1672304486868,"data":[{"T":1672304486865,"s":"BTCUSDT","S":"Buy","v":"0.001","p":"16578.50","L":"PlusTick","i":"20f43950-d8dd-5b31-9112-a178eb6023af","BT":false}]}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct PublicTrade {
+    pub data: Vec<TradeData>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct TradeData {
+    pub p: String,
+}
@@ -0,0 +1,60 @@
+use crate::exit_schedule::PendingExit;
+
+use fraction::Decimal;
+
+// What to do with positions the bot has no exit for when it starts
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OrphanPolicy {
+    Report,
+    Adopt,
+    Flatten,
+}
+
+impl OrphanPolicy {
+    pub fn from_arg(arg: &str) -> Option<OrphanPolicy> {
+        match arg.to_lowercase().as_str() {
+            "report" => Some(OrphanPolicy::Report),
+            "adopt" => Some(OrphanPolicy::Adopt),
+            "flatten" => Some(OrphanPolicy::Flatten),
+            _ => None,
+        }
+    }
+}
+
+// A futures position or spot balance found on the exchange. Futures shorts have a negative qty
+#[derive(Clone, PartialEq, Debug)]
+pub struct Holding {
+    pub symbol: String,
+    pub category: String,
+    pub qty: Decimal,
+    pub entry_price: Decimal,
+}
+
+fn is_tracked(symbol: &str, category: &str, pending_exits: &[PendingExit]) -> bool {
+    pending_exits
+        .iter()
+        .any(|pending_exit| pending_exit.symbol == symbol && pending_exit.category == category)
+}
+
+// Holdings no pending exit is going to close
+pub fn find_orphans(holdings: Vec<Holding>, pending_exits: &[PendingExit]) -> Vec<Holding> {
+    holdings
+        .into_iter()
+        .filter(|holding| holding.qty != Decimal::from(0))
+        .filter(|holding| !is_tracked(&holding.symbol, &holding.category, pending_exits))
+        .collect()
+}
+
+// Symbols with open orders that don't belong to a pending exit, as (symbol, category)
+pub fn find_orphan_orders(
+    open_orders: Vec<(String, String)>,
+    pending_exits: &[PendingExit],
+) -> Vec<(String, String)> {
+    let mut orphan_orders: Vec<(String, String)> = open_orders
+        .into_iter()
+        .filter(|(symbol, category)| !is_tracked(symbol, category, pending_exits))
+        .collect();
+    orphan_orders.sort();
+    orphan_orders.dedup();
+    orphan_orders
+}
@@ -0,0 +1,36 @@
+use tokio::time::Duration;
+
+const DEFAULT_WAIT_SECS: u64 = 15 * 60;
+
+// What happens to running exits on SIGINT or SIGTERM. The feed is always closed first
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ShutdownPolicy {
+    // Close everything at market before exiting
+    Flatten,
+    // Leave the positions as they are. The disconnect cancel all window can't be turned off, so
+    // the exchange cancels what is still resting once it runs out, and the schedule puts the
+    // protection back when it resumes the exits on restart
+    Persist,
+    // Let the exits finish, up to the timeout, and persist whatever is left
+    Wait(Duration),
+}
+
+impl ShutdownPolicy {
+    // "flatten", "persist", "wait" or "wait:<seconds>"
+    pub fn from_arg(arg: &str) -> Option<ShutdownPolicy> {
+        let arg = arg.to_lowercase();
+        match arg.split_once(':') {
+            Some(("wait", secs)) => secs
+                .parse()
+                .ok()
+                .map(|secs| ShutdownPolicy::Wait(Duration::from_secs(secs))),
+            Some(_) => None,
+            None => match arg.as_str() {
+                "flatten" => Some(ShutdownPolicy::Flatten),
+                "persist" => Some(ShutdownPolicy::Persist),
+                "wait" => Some(ShutdownPolicy::Wait(Duration::from_secs(DEFAULT_WAIT_SECS))),
+                _ => None,
+            },
+        }
+    }
+}
@@ -10,14 +10,50 @@ pub struct SymbolInformation {
 #[derive(Deserialize)]
 pub struct ListSymbols {
     pub list: Vec<Symbol>,
+    // Empty on the last page
+    #[serde(default)]
+    pub nextPageCursor: String,
 }
 #[allow(non_snake_case)]
 #[derive(Deserialize)]
 pub struct Symbol {
+    pub symbol: String,
+    // Trading, or PreLaunch, Delivering and Closed when orders can't be placed
+    pub status: String,
+    pub baseCoin: String,
     pub lotSizeFilter: LotSizeFilter,
+    pub priceFilter: PriceFilter,
 }
+// Linear instruments report qtyStep and the market limits, spot instruments report
+// basePrecision and the order amount limits instead
 #[allow(non_snake_case)]
 #[derive(Deserialize)]
 pub struct LotSizeFilter {
+    #[serde(default)]
     pub qtyStep: String,
+    #[serde(default)]
+    pub basePrecision: String,
+    #[serde(default)]
+    pub quotePrecision: String,
+    #[serde(default)]
+    pub minOrderQty: String,
+    #[serde(default)]
+    pub maxOrderQty: String,
+    #[serde(default)]
+    pub maxMktOrderQty: String,
+    #[serde(default)]
+    pub minNotionalValue: String,
+    #[serde(default)]
+    pub minOrderAmt: String,
+    #[serde(default)]
+    pub maxOrderAmt: String,
+}
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct PriceFilter {
+    #[serde(default)]
+    pub minPrice: String,
+    #[serde(default)]
+    pub maxPrice: String,
+    pub tickSize: String,
 }
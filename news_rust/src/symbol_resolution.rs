@@ -0,0 +1,84 @@
+use fraction::Decimal;
+
+// Prefixes venues put in front of a coin whose unit price is too small, and how many coins one
+// unit of the instrument stands for. Longest first so 1000000 isn't read as 1000
+const MULTIPLIERS: &[(&str, u64)] = &[
+    ("1000000", 1_000_000),
+    ("10000", 10_000),
+    ("1000", 1_000),
+    ("1M", 1_000_000),
+];
+
+// How a venue names the instruments of a coin
+pub struct VenueNaming {
+    pub quote: &'static str,
+    // Multiplier prefixes the venue lists the coin under, tried after the plain name
+    pub prefixes: &'static [&'static str],
+}
+
+pub const BYBIT_LINEAR: VenueNaming = VenueNaming {
+    quote: "USDT",
+    prefixes: &["1000", "10000", "1000000"],
+};
+pub const BYBIT_SPOT: VenueNaming = VenueNaming {
+    quote: "USDT",
+    prefixes: &[],
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedSymbol {
+    pub symbol: String,
+    // Coins per unit of the instrument, 1000 for 1000SATSUSDT
+    pub multiplier: Decimal,
+}
+
+// The coin of a ticker and the multiplier its prefix stands for, a prefix with nothing that reads
+// as a coin after it is part of the name (1INCH)
+pub fn split_multiplier(ticker: &str) -> (&str, u64) {
+    for (prefix, multiplier) in MULTIPLIERS {
+        if let Some(coin) = ticker.strip_prefix(prefix) {
+            if coin.len() > 1 && coin.starts_with(|c: char| c.is_ascii_uppercase()) {
+                return (coin, *multiplier);
+            }
+        }
+    }
+    (ticker, 1)
+}
+
+// Instruments an announced ticker could trade as on the venue, the ticker as announced first
+pub fn candidates(ticker: &str, naming: &VenueNaming) -> Vec<ResolvedSymbol> {
+    let (coin, _) = split_multiplier(ticker);
+    let mut names = vec![ticker.to_string(), coin.to_string()];
+    for prefix in naming.prefixes {
+        names.push(format!("{}{}", prefix, coin));
+    }
+    let mut candidates: Vec<ResolvedSymbol> = Vec::new();
+    for name in names {
+        let symbol = format!("{}{}", name, naming.quote);
+        if candidates
+            .iter()
+            .any(|candidate| candidate.symbol == symbol)
+        {
+            continue;
+        }
+        candidates.push(ResolvedSymbol {
+            multiplier: Decimal::from(split_multiplier(&name).1),
+            symbol,
+        });
+    }
+    candidates
+}
+
+// The first candidate the venue lists, the ticker as announced when it lists none
+pub fn resolve(
+    ticker: &str,
+    naming: &VenueNaming,
+    listed: impl Fn(&str) -> bool,
+) -> ResolvedSymbol {
+    let mut candidates = candidates(ticker, naming);
+    let index = candidates
+        .iter()
+        .position(|candidate| listed(&candidate.symbol))
+        .unwrap_or(0);
+    candidates.swap_remove(index)
+}
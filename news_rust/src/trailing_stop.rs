@@ -0,0 +1,142 @@
+use crate::decimal_string;
+use crate::exit_ladder::Side;
+
+use fraction::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// How far the price may fall back from its peak before the trailing stop exits
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Retrace {
+    Pct(#[serde(with = "decimal_string")] Decimal),
+    // ATR over `period` bars of `bar_secs` built from the trade stream, since freshly listed
+    // coins have no kline history to take it from
+    Atr {
+        #[serde(with = "decimal_string")]
+        multiple: Decimal,
+        bar_secs: u64,
+        period: usize,
+    },
+}
+
+impl Retrace {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Retrace::Pct(pct) if pct <= Decimal::from(0) || pct >= Decimal::from(1) => {
+                Err(format!("trailing pct {} is not in (0, 1)", pct))
+            }
+            Retrace::Atr {
+                multiple,
+                bar_secs,
+                period,
+            } if multiple <= Decimal::from(0) || bar_secs == 0 || period == 0 => Err(format!(
+                "trailing ATR needs a positive multiple, bar and period, got {} x {}s x {}",
+                multiple, bar_secs, period
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Bar {
+    index: u64,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+}
+
+pub struct TrailingStop {
+    retrace: Retrace,
+    side: Side,
+    // Best price seen, the low for a short
+    peak: Decimal,
+    bar: Option<Bar>,
+    previous_close: Option<Decimal>,
+    true_ranges: VecDeque<Decimal>,
+}
+
+impl TrailingStop {
+    pub fn new(retrace: Retrace, entry_price: Decimal, side: Side) -> TrailingStop {
+        TrailingStop {
+            retrace,
+            side,
+            peak: entry_price,
+            bar: None,
+            previous_close: None,
+            true_ranges: VecDeque::new(),
+        }
+    }
+
+    pub fn peak(&self) -> Decimal {
+        self.peak
+    }
+
+    // The ATR is only trusted once a full period of bars has closed
+    pub fn atr(&self) -> Option<Decimal> {
+        match self.retrace {
+            Retrace::Atr { period, .. } if self.true_ranges.len() >= period => Some(
+                self.true_ranges
+                    .iter()
+                    .fold(Decimal::from(0), |acc, true_range| acc + *true_range)
+                    / Decimal::from(period),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn stop_price(&self) -> Option<Decimal> {
+        match self.retrace {
+            Retrace::Pct(pct) => Some(self.side.loss(self.peak, pct)),
+            Retrace::Atr { multiple, .. } => self.atr().map(|atr| match self.side {
+                Side::Long => self.peak - atr * multiple,
+                Side::Short => self.peak + atr * multiple,
+            }),
+        }
+    }
+
+    // Feeds a trade `elapsed_ms` after the entry, true once the price has retraced far enough
+    pub fn update(&mut self, price: Decimal, elapsed_ms: u64) -> bool {
+        if let Retrace::Atr {
+            bar_secs, period, ..
+        } = self.retrace
+        {
+            self.update_bars(price, elapsed_ms / (bar_secs * 1000), period);
+        }
+        self.peak = self.side.best(self.peak, price);
+        self.stop_price()
+            .is_some_and(|stop_price| self.side.reached(stop_price, price))
+    }
+
+    fn update_bars(&mut self, price: Decimal, index: u64, period: usize) {
+        match self.bar.as_mut() {
+            Some(bar) if bar.index == index => {
+                bar.high = std::cmp::max(bar.high, price);
+                bar.low = std::cmp::min(bar.low, price);
+                bar.close = price;
+                return;
+            }
+            Some(bar) => {
+                let range = bar.high - bar.low;
+                let true_range = match self.previous_close {
+                    Some(close) => std::cmp::max(
+                        range,
+                        std::cmp::max((bar.high - close).abs(), (bar.low - close).abs()),
+                    ),
+                    None => range,
+                };
+                self.previous_close = Some(bar.close);
+                self.true_ranges.push_back(true_range);
+                if self.true_ranges.len() > period {
+                    self.true_ranges.pop_front();
+                }
+            }
+            None => {}
+        }
+        self.bar = Some(Bar {
+            index,
+            high: price,
+            low: price,
+            close: price,
+        });
+    }
+}
@@ -0,0 +1,27 @@
+use serde::Deserialize;
+//{"retCode":0,"retMsg":"OK","result":{"list":[{"accountType":"UNIFIED","totalEquity":"3.31216591","coin":[{"coin":"BTC","equity":"0.00004798","walletBalance":"0.00004798","locked":"0","availableToWithdraw":"0.00004798"}]}]},"retExtInfo":{},"time":1690872862481}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct WalletBalance {
+    pub result: Result,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Result {
+    pub list: Vec<Account>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Account {
+    pub coin: Vec<Coin>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+pub struct Coin {
+    pub coin: String,
+    pub walletBalance: String,
+}